@@ -0,0 +1,60 @@
+//! Runs the simulation headless with two bots playing, once without rollbacks and once rolling back every frame,
+//! to measure what rollbacks cost. Needs no GPU, so it runs fine on CI:
+//! `cargo run --release --example headless -- 3600`
+
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs, Session};
+use bifrost::ai::{bot_input, Bot, Bots, Difficulty};
+use bifrost::netcode::{start_local_session, FrameCount, GgrsConfig};
+use bifrost::simulation::build_headless_app;
+use std::time::{Duration, Instant};
+
+const DEFAULT_FRAMES: u32 = 3600;
+// How many frames a synctest session resimulates every frame, at most one less than the prediction window of 8
+const CHECK_DISTANCE: usize = 7;
+
+fn main() {
+    let frames = std::env::args()
+        .nth(1)
+        .map(|frames| frames.parse().expect("Frames should be a number."))
+        .unwrap_or(DEFAULT_FRAMES);
+
+    let (simulated, elapsed) = run(frames, start_local_session);
+    report("Without rollbacks", simulated, elapsed);
+
+    let (simulated, elapsed) = run(frames, start_synctest_session);
+    report(&format!("Rolling back {CHECK_DISTANCE} frames every frame"), simulated, elapsed);
+}
+
+/// Runs a headless app for `frames` frames, returning how many it simulated and how long it took.
+fn run<Params>(frames: u32, start_session: impl IntoSystemDescriptor<Params>) -> (u32, Duration) {
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.add_startup_system(start_session)
+        .insert_resource(Bots((0..2).map(|handle| (handle, Bot::new(Difficulty::Hard))).collect()));
+
+    let start = Instant::now();
+    while app.world.resource::<FrameCount>().frame < frames {
+        app.update();
+    }
+
+    (app.world.resource::<FrameCount>().frame, start.elapsed())
+}
+
+fn start_synctest_session(mut commands: Commands) {
+    let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(2)
+        .with_check_distance(CHECK_DISTANCE)
+        .start_synctest_session()
+        .expect("Session failed to start.");
+
+    commands.insert_resource(Session::SyncTestSession(session));
+}
+
+fn report(label: &str, frames: u32, elapsed: Duration) {
+    println!(
+        "{label}: {frames} frames in {:.2?}, {:.1}µs per frame",
+        elapsed,
+        elapsed.as_secs_f64() * 1e6 / frames as f64,
+    );
+}
@@ -0,0 +1,184 @@
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use bevy_ggrs::{ggrs::PlayerHandle, Session};
+use std::str::FromStr;
+use crate::components::*;
+use crate::input::PlayerInput;
+use crate::netcode::{FrameCount, GgrsConfig};
+use crate::simulation::*;
+
+
+/// Players driven by a bot instead of the keyboard, by player handle.
+/// Bots are only ever asked for the inputs of local players, so registering one for a remote handle is harmless.
+#[derive(Resource, Default)]
+pub struct Bots(pub HashMap<PlayerHandle, Bot>);
+
+/// Everything bots look at to decide their input.
+#[derive(SystemParam)]
+pub struct BotInput<'w, 's> {
+    frame_count: Res<'w, FrameCount>,
+    frame_rate: Res<'w, FrameRate>,
+    session: Option<Res<'w, Session<GgrsConfig>>>,
+    bots: ResMut<'w, Bots>,
+    ball_query: Query<'w, 's, (&'static Transform, &'static Velocity), With<Ball>>,
+    paddle_query: Query<'w, 's, (&'static Transform, &'static Player)>,
+}
+
+impl<'w, 's> BotInput<'w, 's> {
+    /// Asks the bot playing `handle` for its input, if that player is a bot.
+    pub fn input(&mut self, handle: PlayerHandle) -> Option<u8> {
+        // A new session plays a world that was reset, loaded or resumed, with its frames counted anew.
+        // Whatever the bots decided for the last one no longer holds
+        if self.session.as_ref().is_some_and(|session| session.is_added()) {
+            for bot in self.bots.0.values_mut() {
+                *bot = Bot::new(bot.difficulty);
+            }
+        }
+
+        let bot = self.bots.0.get_mut(&handle)?;
+        let (ball_transform, ball_velocity) = self.ball_query.single();
+        let paddle_transform = self
+            .paddle_query
+            .iter()
+            .find_map(|(transform, player)| (player.handle == handle).then_some(transform))
+            .expect("Every player should have a paddle.");
+
+        Some(bot.input(
+            handle,
+            self.frame_count.frame,
+            paddle_transform.translation,
+            ball_transform.translation,
+            ball_velocity.0,
+            *self.frame_rate,
+        ))
+    }
+}
+
+/// Input system for sessions without a keyboard, where bots play every local player. Players without a bot stand still.
+pub fn bot_input(handle: In<PlayerHandle>, mut bot_input: BotInput) -> u8 {
+    bot_input.input(handle.0).unwrap_or_default()
+}
+
+/// How well a bot plays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// Frames the bot waits before re-reading the ball's trajectory, as long at any frame rate.
+    fn reaction_delay(&self, frame_rate: FrameRate) -> u32 {
+        let frames_at_default_rate = match self {
+            Difficulty::Easy => 20,
+            Difficulty::Normal => 10,
+            Difficulty::Hard => 3,
+        };
+        frames_at_default_rate * frame_rate.0 as u32 / FRAME_RATE as u32
+    }
+
+    /// Furthest the bot may misjudge the ball's intercept by.
+    fn max_error(&self) -> f32 {
+        match self {
+            Difficulty::Easy => PADDLE_SIZE.x,
+            Difficulty::Normal => PADDLE_SIZE.x / 3.0,
+            Difficulty::Hard => PADDLE_SIZE.x / 10.0,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "" | "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("Unknown difficulty: {s}")),
+        }
+    }
+}
+
+/// A deterministic paddle controller, given the same frames it always produces the same inputs.
+#[derive(Clone, Debug)]
+pub struct Bot {
+    difficulty: Difficulty,
+    target_x: f32,
+    next_decision: u32,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty) -> Bot {
+        Bot {
+            difficulty,
+            target_x: 0.0,
+            next_decision: 0,
+        }
+    }
+
+    /// Decides the bot's input for a frame by steering its paddle towards where the ball will cross it.
+    pub fn input(
+        &mut self,
+        handle: PlayerHandle,
+        frame: u32,
+        paddle: Vec3,
+        ball: Vec3,
+        ball_velocity: Vec2,
+        frame_rate: FrameRate,
+    ) -> u8 {
+        if frame >= self.next_decision {
+            self.next_decision = frame + self.difficulty.reaction_delay(frame_rate);
+            // Shadow the ball while it's heading away, there's nothing to intercept yet
+            self.target_x = intercept(paddle, ball, ball_velocity).unwrap_or(ball.x) + self.error(handle, frame);
+        }
+
+        // Closer than a frame's move, the paddle would only overshoot
+        let step = PLAYER_MOVE_SPEED * frame_rate.time_step();
+        let offset = self.target_x - paddle.x;
+        if offset > step {
+            PlayerInput::RIGHT.bits()
+        } else if offset < -step {
+            PlayerInput::LEFT.bits()
+        } else {
+            0
+        }
+    }
+
+    /// How far off the bot's guess is for this frame, derived from the frame so that replays make the same mistakes.
+    fn error(&self, handle: PlayerHandle, frame: u32) -> f32 {
+        let mut hash = (frame as u64) << 8 | handle as u64;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+
+        // Map the hash onto [-1, 1]
+        let unit = (hash % 2001) as f32 / 1000.0 - 1.0;
+        unit * self.difficulty.max_error()
+    }
+}
+
+/// Predicts the x coordinate where the ball reaches the paddle, bouncing off the side walls along the way.
+/// Returns `None` if the ball is moving away from the paddle.
+fn intercept(paddle: Vec3, ball: Vec3, ball_velocity: Vec2) -> Option<f32> {
+    // The ball meets the paddle's face rather than its center
+    let contact_y = if ball.y > paddle.y {
+        paddle.y + (PADDLE_SIZE.y + BALL_SIZE.y) / 2.0
+    } else {
+        paddle.y - (PADDLE_SIZE.y + BALL_SIZE.y) / 2.0
+    };
+
+    let time = (contact_y - ball.y) / ball_velocity.y;
+    if !time.is_finite() || time < 0.0 {
+        return None;
+    }
+
+    // Unfold the trajectory: every bounce off a side wall mirrors the remaining path
+    let left = LEFT_WALL + (WALL_THICKNESS + BALL_SIZE.x) / 2.0;
+    let right = RIGHT_WALL - (WALL_THICKNESS + BALL_SIZE.x) / 2.0;
+    let width = right - left;
+    let travelled = (ball.x + ball_velocity.x * time - left).rem_euclid(2.0 * width);
+
+    Some(if travelled > width { right - (travelled - width) } else { left + travelled })
+}
@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+
+/// Where the files served to browsers live.
+pub struct Files {
+    /// The page and the wasm bundle built for it
+    pub web: PathBuf,
+    /// The game's assets, which it requests under `/assets/`
+    pub assets: PathBuf,
+}
+
+impl Files {
+    /// Answers a request for a file, then closes the connection.
+    pub fn serve(&self, mut stream: TcpStream, path: &str) {
+        let response = match self.resolve(path).and_then(|file| Some((std::fs::read(&file).ok()?, file))) {
+            Some((body, file)) => {
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    content_type(&file),
+                    body.len()
+                )
+                .into_bytes();
+                response.extend(body);
+                response
+            }
+            None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+        };
+
+        if let Err(err) = stream.write_all(&response) {
+            eprintln!("Failed to serve {path}: {err}");
+        }
+    }
+
+    /// Maps a request path to a file, refusing anything that would climb out of the served directories.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = path.split(['?', '#']).next()?;
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty()).peekable();
+        if segments.clone().any(|segment| segment == ".." || segment.contains('\\')) {
+            return None;
+        }
+
+        let mut file = match segments.peek() {
+            Some(&"assets") => {
+                segments.next();
+                self.assets.clone()
+            }
+            _ => self.web.clone(),
+        };
+        file.extend(segments);
+
+        if file.is_dir() {
+            file.push("index.html");
+        }
+        file.is_file().then_some(file)
+    }
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        // Browsers only compile wasm as it streams in when it's served as such
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("ogg") => "audio/ogg",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
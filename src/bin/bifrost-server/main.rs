@@ -0,0 +1,145 @@
+//! Serves the game to browsers and hosts the signaling that lets players find each other,
+//! so a whole setup is one command and works on a LAN without internet access.
+//! The signaling speaks the same protocol as `matchbox_server`, either can stand in for the other.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod files;
+#[cfg(not(target_arch = "wasm32"))]
+mod signaling;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    server::run();
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    panic!("bifrost-server only runs natively, it serves the game to browsers.");
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod server {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use crate::files::Files;
+    use crate::signaling::Signaling;
+
+    // The port the game looks for signaling on, serving the game there too saves opening a second one
+    const DEFAULT_PORT: u16 = 3536;
+    const USAGE: &str = "Usage: bifrost-server [--port=PORT] [--web=DIR] [--assets=DIR]";
+    // Requests with longer headers are turned away
+    const MAX_HEADER_SIZE: usize = 8192;
+    const MAX_HEADERS: usize = 64;
+
+    /// A request's path and the headers we care about.
+    pub struct Request {
+        pub path: String,
+        websocket_key: Option<String>,
+    }
+
+    /// Options as `--key=value` pairs.
+    fn arg(key: &str) -> Option<String> {
+        std::env::args().skip(1).find_map(|arg| {
+            let (k, value) = arg.trim_start_matches("--").split_once('=')?;
+            (k == key).then(|| value.to_owned())
+        })
+    }
+
+    /// Reports an option the server can't start with along with the usage, and exits.
+    fn exit_with_usage(message: &str) -> ! {
+        eprintln!("{message}\n{USAGE}");
+        std::process::exit(2);
+    }
+
+    pub fn run() {
+        let port = arg("port").map_or(DEFAULT_PORT, |port| {
+            port.parse()
+                .unwrap_or_else(|err| exit_with_usage(&format!("Invalid --port {port:?}: {err}, it needs a port number.")))
+        });
+        let files = Files {
+            web: arg("web").map_or_else(|| PathBuf::from("web"), PathBuf::from),
+            assets: arg("assets").map_or_else(|| PathBuf::from("assets"), PathBuf::from),
+        };
+        let files = Arc::new(files);
+        let signaling = Arc::new(Mutex::new(Signaling::default()));
+
+        let listener = TcpListener::bind(("0.0.0.0", port)).unwrap_or_else(|err| {
+            eprintln!("Failed to listen on port {port}: {err}");
+            std::process::exit(1);
+        });
+        println!("Serving the game on http://0.0.0.0:{port}/, signaling on ws://0.0.0.0:{port}/<room>");
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Failed to accept a connection: {err}");
+                    continue;
+                }
+            };
+
+            let files = files.clone();
+            let signaling = signaling.clone();
+            std::thread::spawn(move || handle_connection(stream, &files, &signaling));
+        }
+    }
+
+    fn handle_connection(mut stream: TcpStream, files: &Files, signaling: &Mutex<Signaling>) {
+        let request = match read_request(&mut stream) {
+            Ok(request) => request,
+            Err(err) => {
+                eprintln!("Dropping a bad request: {err}");
+                return;
+            }
+        };
+
+        match request.websocket_key {
+            Some(ref key) => {
+                let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+                );
+                if stream.write_all(response.as_bytes()).is_ok() {
+                    crate::signaling::serve_peer(stream, &request.path, signaling);
+                }
+            }
+            None => files.serve(stream, &request.path),
+        }
+    }
+
+    fn read_request(stream: &mut TcpStream) -> Result<Request, String> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            let len = stream.read(&mut chunk).map_err(|err| err.to_string())?;
+            if len == 0 {
+                return Err("connection closed".to_owned());
+            }
+            buffer.extend_from_slice(&chunk[..len]);
+
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut request = httparse::Request::new(&mut headers);
+            match request.parse(&buffer).map_err(|err| err.to_string())? {
+                httparse::Status::Complete(_) => {
+                    let path = request.path.ok_or("no path")?.to_owned();
+                    let header = |name: &str| {
+                        request
+                            .headers
+                            .iter()
+                            .find(|header| header.name.eq_ignore_ascii_case(name))
+                            .and_then(|header| std::str::from_utf8(header.value).ok())
+                    };
+                    let upgrade = header("Upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+                    let websocket_key = header("Sec-WebSocket-Key").filter(|_| upgrade).map(str::to_owned);
+                    return Ok(Request { path, websocket_key });
+                }
+                httparse::Status::Partial if buffer.len() > MAX_HEADER_SIZE => {
+                    return Err("headers too long".to_owned());
+                }
+                httparse::Status::Partial => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+use tungstenite::{protocol::Role, Message, WebSocket};
+
+
+// Signaling
+// Introduces the peers of a room to each other and relays their WebRTC offers, answers and ICE candidates,
+// after which they talk directly. Matches `matchbox_server`'s rooms: `/<room>` lets everyone in,
+// `/<room>?next=N` groups peers into rooms of N in the order they arrive.
+
+// How long a peer's connection waits for them to say something before checking for messages to pass on
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+type PeerId = String;
+
+/// What the server tells a peer.
+#[derive(Serialize)]
+enum PeerEvent {
+    NewPeer(PeerId),
+    Signal { sender: PeerId, data: serde_json::Value },
+}
+
+/// What a peer asks of the server.
+#[derive(Deserialize)]
+enum PeerRequest {
+    Uuid(PeerId),
+    Signal { receiver: PeerId, data: serde_json::Value },
+    KeepAlive,
+}
+
+/// A room as requested in the URL.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RoomId {
+    id: String,
+    /// Split the room into groups of this many peers
+    next: Option<usize>,
+}
+
+impl RoomId {
+    fn from_path(path: &str) -> RoomId {
+        let (id, query) = path.split_once('?').unwrap_or((path, ""));
+        let next = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("next="))
+            .and_then(|next| next.parse().ok());
+
+        RoomId {
+            id: id.trim_matches('/').to_owned(),
+            next,
+        }
+    }
+}
+
+/// The peers connected so far and the rooms they're in.
+#[derive(Default)]
+pub struct Signaling {
+    peers: HashMap<PeerId, Sender<String>>,
+    /// Peers waiting in each room, for whoever joins next
+    rooms: HashMap<RoomId, HashSet<PeerId>>,
+}
+
+impl Signaling {
+    /// Adds a peer to a room, returning the peers already in it.
+    fn join(&mut self, peer: PeerId, room: RoomId, sender: Sender<String>) -> Vec<PeerId> {
+        self.peers.insert(peer.clone(), sender);
+        let peers = self.rooms.entry(room.clone()).or_default();
+        let previous_peers = peers.iter().cloned().collect();
+
+        match room.next {
+            // The group is complete, the next peer starts a new one
+            Some(size) if peers.len() + 1 >= size => peers.clear(),
+            _ => {
+                peers.insert(peer);
+            }
+        }
+        previous_peers
+    }
+
+    fn leave(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        for peers in self.rooms.values_mut() {
+            peers.remove(peer);
+        }
+        self.rooms.retain(|_, peers| !peers.is_empty());
+    }
+
+    fn send(&self, peer: &PeerId, event: &PeerEvent) {
+        if let Some(sender) = self.peers.get(peer) {
+            let event = serde_json::to_string(event).expect("Peer events always serialize");
+            // A peer that's gone has its connection clean up after it
+            sender.send(event).ok();
+        }
+    }
+}
+
+/// Relays a peer's signaling until they disconnect.
+pub fn serve_peer(stream: TcpStream, path: &str, signaling: &Mutex<Signaling>) {
+    if let Err(err) = stream.set_read_timeout(Some(POLL_INTERVAL)) {
+        eprintln!("Failed to set up a peer connection: {err}");
+        return;
+    }
+
+    let room = RoomId::from_path(path);
+    let mut websocket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let (sender, receiver) = mpsc::channel();
+    let mut peer = None;
+
+    loop {
+        match websocket.read_message() {
+            Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                Ok(request) => {
+                    let mut signaling = signaling.lock().expect("Signaling lock poisoned");
+                    handle_request(request, &mut peer, &room, &sender, &mut signaling);
+                }
+                Err(err) => eprintln!("Dropping a bad request {text:?}: {err}"),
+            },
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err)) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+
+        if !pass_on_events(&mut websocket, &receiver) {
+            break;
+        }
+    }
+
+    if let Some(peer) = peer {
+        println!("{peer} left {}", room.id);
+        signaling.lock().expect("Signaling lock poisoned").leave(&peer);
+    }
+}
+
+fn handle_request(
+    request: PeerRequest,
+    peer: &mut Option<PeerId>,
+    room: &RoomId,
+    sender: &Sender<String>,
+    signaling: &mut Signaling,
+) {
+    match request {
+        PeerRequest::Uuid(id) if peer.is_none() => {
+            println!("{id} joined {}", room.id);
+            for previous_peer in signaling.join(id.clone(), room.clone(), sender.clone()) {
+                signaling.send(&previous_peer, &PeerEvent::NewPeer(id.clone()));
+            }
+            *peer = Some(id);
+        }
+        PeerRequest::Uuid(_) => eprintln!("Ignoring a peer introducing themselves twice"),
+        PeerRequest::Signal { receiver, data } => {
+            let Some(sender) = peer.clone() else {
+                eprintln!("Ignoring a signal from a peer that hasn't introduced themselves");
+                return;
+            };
+            signaling.send(&receiver, &PeerEvent::Signal { sender, data });
+        }
+        PeerRequest::KeepAlive => {}
+    }
+}
+
+/// Sends the peer what others had for them, returning whether they're still connected.
+fn pass_on_events(websocket: &mut WebSocket<TcpStream>, receiver: &Receiver<String>) -> bool {
+    for event in receiver.try_iter() {
+        if websocket.write_message(Message::Text(event)).is_err() {
+            return false;
+        }
+    }
+    match websocket.write_pending() {
+        Err(tungstenite::Error::Io(err)) => matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        result => result.is_ok(),
+    }
+}
@@ -0,0 +1,236 @@
+use bevy::prelude::*;
+use bevy_ggrs::ggrs::{Message, NonBlockingSocket};
+use matchbox_socket::WebRtcSocket;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use crate::simulation::GameMode;
+
+
+// Channels
+// GGRS takes a socket for itself, but the game has more to say to its peers than inputs.
+// Every packet is tagged with the channel it belongs to, so several channels can share one socket,
+// and a packet GGRS can't make sense of never reaches it.
+
+// Packets waiting on a channel nobody reads are dropped beyond this, oldest first
+const MAX_QUEUED_PACKETS: usize = 1024;
+
+/// The channels sharing a socket, the discriminant tags each packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Channel {
+    /// GGRS' own messages
+    Ggrs,
+    /// Messages between the games themselves, outside the rollback world
+    Control,
+    /// What the players type to each other
+    Chat,
+}
+
+impl Channel {
+    const ALL: [Channel; 3] = [Channel::Ggrs, Channel::Control, Channel::Chat];
+
+    fn from_tag(tag: u8) -> Option<Channel> {
+        Channel::ALL.into_iter().find(|channel| *channel as u8 == tag)
+    }
+}
+
+/// Anything that can exchange packets with peers addressed by strings.
+pub trait Transport: Send + 'static {
+    fn send(&mut self, packet: Box<[u8]>, peer: &str);
+    fn receive(&mut self) -> Vec<(String, Box<[u8]>)>;
+}
+
+impl Transport for WebRtcSocket {
+    fn send(&mut self, packet: Box<[u8]>, peer: &str) {
+        WebRtcSocket::send(self, packet, peer);
+    }
+
+    fn receive(&mut self) -> Vec<(String, Box<[u8]>)> {
+        WebRtcSocket::receive(self)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Transport for std::net::UdpSocket {
+    fn send(&mut self, packet: Box<[u8]>, peer: &str) {
+        match peer.parse::<std::net::SocketAddr>() {
+            Ok(addr) => {
+                if let Err(err) = self.send_to(&packet, addr) {
+                    warn!("Failed to send to {}: {}", addr, err);
+                }
+            }
+            Err(err) => warn!("Invalid peer address {:?}: {}", peer, err),
+        }
+    }
+
+    fn receive(&mut self) -> Vec<(String, Box<[u8]>)> {
+        // Large enough for any datagram
+        let mut buffer = [0; 65536];
+        let mut packets = Vec::new();
+        loop {
+            match self.recv_from(&mut buffer) {
+                Ok((len, addr)) => packets.push((addr.to_string(), buffer[..len].into())),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                // The peer not listening yet shows up as an error on some platforms, there's nothing to do but wait
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionReset => continue,
+                Err(err) => {
+                    warn!("Failed to receive: {}", err);
+                    break;
+                }
+            }
+        }
+        packets
+    }
+}
+
+struct Multiplexer {
+    transport: Box<dyn Transport>,
+    /// Packets received but not read yet, by channel
+    inboxes: [Vec<(String, Box<[u8]>)>; Channel::ALL.len()],
+    /// GGRS sessions started on this socket so far
+    ggrs_sessions: u8,
+}
+
+impl Multiplexer {
+    fn sort_incoming(&mut self) {
+        for (peer, packet) in self.transport.receive() {
+            let Some((&tag, _)) = packet.split_first() else {
+                continue;
+            };
+            let Some(channel) = Channel::from_tag(tag) else {
+                warn!("Dropping a packet from {} on unknown channel {}", peer, tag);
+                continue;
+            };
+
+            let inbox = &mut self.inboxes[channel as usize];
+            if inbox.len() == MAX_QUEUED_PACKETS {
+                inbox.remove(0);
+            }
+            inbox.push((peer, packet));
+        }
+    }
+}
+
+/// A socket shared by every channel, cloning it hands out another handle to the same socket.
+#[derive(Resource, Clone)]
+pub struct SharedSocket(Arc<Mutex<Multiplexer>>);
+
+impl SharedSocket {
+    pub fn new(transport: impl Transport) -> SharedSocket {
+        SharedSocket(Arc::new(Mutex::new(Multiplexer {
+            transport: Box::new(transport),
+            inboxes: Default::default(),
+            ggrs_sessions: 0,
+        })))
+    }
+
+    /// Sends a packet to a peer on the given channel.
+    pub fn send(&self, channel: Channel, payload: &[u8], peer: &str) {
+        let mut packet = Vec::with_capacity(payload.len() + 1);
+        packet.push(channel as u8);
+        packet.extend_from_slice(payload);
+
+        let mut multiplexer = self.0.lock().expect("Socket lock poisoned");
+        multiplexer.transport.send(packet.into_boxed_slice(), peer);
+    }
+
+    /// Takes every packet received on the given channel so far, without their tags.
+    pub fn receive(&self, channel: Channel) -> Vec<(String, Box<[u8]>)> {
+        let mut multiplexer = self.0.lock().expect("Socket lock poisoned");
+        multiplexer.sort_incoming();
+        std::mem::take(&mut multiplexer.inboxes[channel as usize])
+            .into_iter()
+            .map(|(peer, packet)| (peer, packet[1..].into()))
+            .collect()
+    }
+
+    /// Serializes a control message and sends it to a peer.
+    pub fn send_control(&self, message: &ControlMessage, peer: &str) {
+        let payload = bincode::serialize(message).expect("Control messages always serialize");
+        self.send(Channel::Control, &payload, peer);
+    }
+
+    /// The socket to start a GGRS session with.
+    /// Each call starts a new session, whose packets never reach the earlier ones. Peers count their sessions alike
+    /// as long as they start them one for one, so a straggling packet of the last round can't confuse the next.
+    pub fn ggrs_socket(&self) -> GgrsSocket {
+        let mut multiplexer = self.0.lock().expect("Socket lock poisoned");
+        multiplexer.ggrs_sessions = multiplexer.ggrs_sessions.wrapping_add(1);
+        GgrsSocket {
+            socket: self.clone(),
+            session: multiplexer.ggrs_sessions,
+        }
+    }
+}
+
+/// The GGRS channel of a shared socket, for one session.
+pub struct GgrsSocket {
+    socket: SharedSocket,
+    /// Tags every packet after the channel's, packets of other sessions are dropped
+    session: u8,
+}
+
+impl NonBlockingSocket<String> for GgrsSocket {
+    fn send_to(&mut self, msg: &Message, addr: &String) {
+        let mut payload = vec![self.session];
+        bincode::serialize_into(&mut payload, msg).expect("GGRS messages always serialize");
+        self.socket.send(Channel::Ggrs, &payload, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(String, Message)> {
+        self.socket
+            .receive(Channel::Ggrs)
+            .into_iter()
+            // Should the peer start the next session first, GGRS sends whatever gets dropped here again
+            .filter(|(_, packet)| packet.first() == Some(&self.session))
+            .filter_map(|(peer, packet)| match bincode::deserialize(&packet[1..]) {
+                Ok(msg) => Some((peer, msg)),
+                Err(err) => {
+                    warn!("Dropping a malformed GGRS message from {}: {}", peer, err);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// What the games tell each other outside of GGRS.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlMessage {
+    /// Asks for a `Pong` to measure the round trip time.
+    /// Carries the sender's round trip time once it has settled on one, so the peer learns it even if packets get lost,
+    /// and which of the sender's measurements that is, as the latency may be measured again every round.
+    Ping { seq: u32, measurement: u32, rtt_ms: Option<u32> },
+    /// Answers the `Ping` with the same `seq`.
+    Pong { seq: u32, measurement: u32, rtt_ms: Option<u32> },
+    /// The sender is ready to start the given round, counting every round played over the connection.
+    NextRound { round: u32 },
+    /// Introduces a newly connected peer, with the frame and id of the match they'd resume if they have one,
+    /// and the mode they'd like a new match in unless they leave it to us.
+    /// The nonce settles which match resumes should both have one. Until `heard` says they've heard us, answer with ours.
+    Hello { resume: Option<(u32, u64)>, mode: Option<GameMode>, nonce: u64, heard: bool },
+    /// A piece of the match being resumed, out of `count`.
+    ResumeChunk { index: u32, count: u32, bytes: Vec<u8> },
+    /// Confirms the `ResumeChunk` with the same `index` arrived, so it isn't sent again.
+    ResumeAck { index: u32 },
+}
+
+/// A control message received from a peer.
+pub struct ControlEvent {
+    pub peer: String,
+    pub message: ControlMessage,
+}
+
+/// Hands the control messages received since the last update to whoever is interested, as `ControlEvent`s.
+pub fn receive_control_messages(socket: Option<Res<SharedSocket>>, mut events: EventWriter<ControlEvent>) {
+    let Some(socket) = socket else {
+        return;
+    };
+
+    for (peer, packet) in socket.receive(Channel::Control) {
+        match bincode::deserialize(&packet) {
+            Ok(message) => events.send(ControlEvent { peer, message }),
+            Err(err) => warn!("Dropping a malformed control message from {}: {}", peer, err),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use bevy::{
+    prelude::*,
+    utils::{Duration, HashSet, Instant},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use crate::channels::{Channel, SharedSocket};
+use crate::netcode::RemotePeer;
+
+
+// Chat
+// Lets the players type to each other during a match, over the same socket as their inputs but on a channel of its own.
+// Messages live outside the rollback world, so they're never predicted, rolled back or resimulated.
+// The socket may lose packets, so messages are sent again until the other player acknowledges them.
+
+const CHAT_OPEN_KEY: KeyCode = KeyCode::Return;
+const MAX_MESSAGE_LENGTH: usize = 120;
+// Lines kept around, and how many of the latest are shown
+const HISTORY_LENGTH: usize = 100;
+const VISIBLE_LINES: usize = 8;
+// Each player may send this many messages within the window, anything beyond is refused
+const RATE_LIMIT_MESSAGES: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+const RESEND_INTERVAL: Duration = Duration::from_millis(250);
+
+const CHAT_FONT_SIZE: f32 = 20.0;
+const CHAT_TEXT_PADDING: Val = Val::Px(5.0);
+const CHAT_BACKGROUND_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.6);
+const LOCAL_NAME_COLOR: Color = Color::rgb(0.0, 0.47, 1.0);
+const REMOTE_NAME_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
+const MESSAGE_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const NOTICE_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+
+
+/// What travels over the chat channel.
+#[derive(Serialize, Deserialize)]
+enum ChatPacket {
+    Message { seq: u32, text: String },
+    /// Confirms the message with the same `seq` arrived, so it isn't sent again.
+    Ack { seq: u32 },
+}
+
+enum Author {
+    Local,
+    Remote,
+    /// Said by the game itself, like a warning to slow down
+    Notice,
+}
+
+struct ChatLine {
+    author: Author,
+    text: String,
+}
+
+/// A message on its way to the other player.
+struct Outgoing {
+    seq: u32,
+    text: String,
+    last_sent: Instant,
+}
+
+/// The conversation so far and what's being typed.
+#[derive(Resource, Default)]
+pub struct Chat {
+    history: VecDeque<ChatLine>,
+    /// What's typed so far, while the chat box is open
+    typing: Option<String>,
+    next_seq: u32,
+    unacknowledged: Vec<Outgoing>,
+    received: HashSet<u32>,
+    /// When the recent messages each way were sent, for rate limiting
+    sent_times: VecDeque<Instant>,
+    received_times: VecDeque<Instant>,
+}
+
+impl Chat {
+    /// Whether the keyboard belongs to the chat box rather than the paddle.
+    pub fn is_typing(&self) -> bool {
+        self.typing.is_some()
+    }
+
+    fn push_line(&mut self, author: Author, text: String) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(ChatLine { author, text });
+    }
+
+    /// Forgets everything about the previous connection, the new one numbers its messages from scratch.
+    fn reset_connection(&mut self) {
+        self.next_seq = 0;
+        self.unacknowledged.clear();
+        self.received.clear();
+        self.sent_times.clear();
+        self.received_times.clear();
+    }
+}
+
+/// Records a message in the rate limiter's window, unless the window is full already.
+fn within_rate_limit(times: &mut VecDeque<Instant>, now: Instant) -> bool {
+    while times.front().is_some_and(|time| now.duration_since(*time) > RATE_LIMIT_WINDOW) {
+        times.pop_front();
+    }
+    if times.len() >= RATE_LIMIT_MESSAGES {
+        return false;
+    }
+    times.push_back(now);
+    true
+}
+
+fn send_packet(socket: &SharedSocket, peer: &RemotePeer, packet: &ChatPacket) {
+    let payload = bincode::serialize(packet).expect("Chat packets always serialize");
+    socket.send(Channel::Chat, &payload, &peer.0);
+}
+
+#[derive(Component)]
+struct ChatBox;
+
+#[derive(Component)]
+struct ChatHistoryText;
+
+#[derive(Component)]
+struct ChatInputText;
+
+fn spawn_chat_box(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = TextStyle {
+        font: asset_server.load("../assets/fonts/FiraMono-Medium.ttf"),
+        font_size: CHAT_FONT_SIZE,
+        color: MESSAGE_COLOR,
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: CHAT_TEXT_PADDING,
+                        left: CHAT_TEXT_PADDING,
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(CHAT_TEXT_PADDING),
+                    display: Display::None,
+                    ..default()
+                },
+                background_color: CHAT_BACKGROUND_COLOR.into(),
+                ..default()
+            },
+            ChatBox,
+        ))
+        .with_children(|parent| {
+            parent.spawn((TextBundle::from_section("", style.clone()), ChatHistoryText));
+            parent.spawn((TextBundle::from_section("", style), ChatInputText));
+        });
+}
+
+/// Takes in the other player's messages and acknowledgements.
+fn receive_chat(
+    socket: Option<Res<SharedSocket>>,
+    peer: Option<Res<RemotePeer>>,
+    mut chat: ResMut<Chat>,
+) {
+    let (Some(socket), Some(peer)) = (socket, peer) else {
+        return;
+    };
+    if peer.is_added() {
+        chat.reset_connection();
+    }
+
+    for (sender, packet) in socket.receive(Channel::Chat) {
+        if sender != peer.0 {
+            continue;
+        }
+
+        match bincode::deserialize(&packet) {
+            Ok(ChatPacket::Message { seq, text }) => {
+                // Acknowledge every copy, the acknowledgement of the first may have been lost
+                if chat.received.contains(&seq) {
+                    send_packet(&socket, &peer, &ChatPacket::Ack { seq });
+                    continue;
+                }
+
+                // A message over the limit goes unacknowledged, so it comes again once the limit allows it
+                let now = Instant::now();
+                if !within_rate_limit(&mut chat.received_times, now) {
+                    continue;
+                }
+                chat.received.insert(seq);
+                send_packet(&socket, &peer, &ChatPacket::Ack { seq });
+                let text: String = text.chars().filter(|c| !c.is_control()).take(MAX_MESSAGE_LENGTH).collect();
+                chat.push_line(Author::Remote, text);
+            }
+            Ok(ChatPacket::Ack { seq }) => chat.unacknowledged.retain(|outgoing| outgoing.seq != seq),
+            Err(err) => warn!("Dropping a malformed chat packet from {}: {}", sender, err),
+        }
+    }
+}
+
+/// Sends the messages the other player hasn't acknowledged yet again.
+fn resend_chat(socket: Option<Res<SharedSocket>>, peer: Option<Res<RemotePeer>>, mut chat: ResMut<Chat>) {
+    let (Some(socket), Some(peer)) = (socket, peer) else {
+        return;
+    };
+
+    let now = Instant::now();
+    let due = |outgoing: &Outgoing| now.duration_since(outgoing.last_sent) >= RESEND_INTERVAL;
+    // Only touch the chat when there's something to resend, touching it redraws the chat box
+    if !chat.unacknowledged.iter().any(due) {
+        return;
+    }
+
+    for outgoing in chat.unacknowledged.iter_mut() {
+        if due(outgoing) {
+            outgoing.last_sent = now;
+            let packet = ChatPacket::Message {
+                seq: outgoing.seq,
+                text: outgoing.text.clone(),
+            };
+            send_packet(&socket, &peer, &packet);
+        }
+    }
+}
+
+/// Enter opens the chat box, typing fills it and Enter again sends what was typed.
+fn type_chat(
+    mut characters: EventReader<ReceivedCharacter>,
+    keys: Res<Input<KeyCode>>,
+    socket: Option<Res<SharedSocket>>,
+    peer: Option<Res<RemotePeer>>,
+    mut chat: ResMut<Chat>,
+) {
+    let (Some(socket), Some(peer)) = (socket, peer) else {
+        characters.clear();
+        return;
+    };
+
+    if !chat.is_typing() {
+        characters.clear();
+        if keys.just_pressed(CHAT_OPEN_KEY) {
+            chat.typing = Some(String::new());
+        }
+        return;
+    }
+    if characters.is_empty() && !keys.any_just_pressed([KeyCode::Back, CHAT_OPEN_KEY]) {
+        return;
+    }
+    let Some(typing) = &mut chat.typing else {
+        return;
+    };
+
+    for character in characters.iter() {
+        if !character.char.is_control() && typing.chars().count() < MAX_MESSAGE_LENGTH {
+            typing.push(character.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        typing.pop();
+    }
+    if !keys.just_pressed(CHAT_OPEN_KEY) {
+        return;
+    }
+
+    let text = chat.typing.take().unwrap_or_default().trim().to_owned();
+    if text.is_empty() {
+        return;
+    }
+
+    let now = Instant::now();
+    if !within_rate_limit(&mut chat.sent_times, now) {
+        chat.push_line(Author::Notice, "Slow down, message not sent".to_owned());
+        return;
+    }
+
+    let seq = chat.next_seq;
+    chat.next_seq += 1;
+    send_packet(&socket, &peer, &ChatPacket::Message { seq, text: text.clone() });
+    chat.unacknowledged.push(Outgoing {
+        seq,
+        text: text.clone(),
+        last_sent: now,
+    });
+    chat.push_line(Author::Local, text);
+}
+
+fn update_chat_box(
+    chat: Res<Chat>,
+    peer: Option<Res<RemotePeer>>,
+    mut box_query: Query<&mut Style, With<ChatBox>>,
+    mut history_query: Query<&mut Text, (With<ChatHistoryText>, Without<ChatInputText>)>,
+    mut input_query: Query<&mut Text, (With<ChatInputText>, Without<ChatHistoryText>)>,
+) {
+    // Only matches against another player have someone to chat with
+    let display = if peer.is_some() { Display::Flex } else { Display::None };
+    for mut style in &mut box_query {
+        style.display = display;
+    }
+    if !chat.is_changed() {
+        return;
+    }
+
+    for mut text in &mut history_query {
+        let style = text.sections[0].style.clone();
+        let section = |value: String, color: Color| TextSection::new(value, TextStyle { color, ..style.clone() });
+
+        let lines = chat.history.iter().skip(chat.history.len().saturating_sub(VISIBLE_LINES));
+        let mut sections: Vec<TextSection> = lines
+            .flat_map(|line| match line.author {
+                Author::Local => vec![section("You: ".to_owned(), LOCAL_NAME_COLOR), section(format!("{}\n", line.text), MESSAGE_COLOR)],
+                Author::Remote => vec![section("Them: ".to_owned(), REMOTE_NAME_COLOR), section(format!("{}\n", line.text), MESSAGE_COLOR)],
+                Author::Notice => vec![section(format!("{}\n", line.text), NOTICE_COLOR)],
+            })
+            .collect();
+        if sections.is_empty() {
+            sections.push(section(String::new(), MESSAGE_COLOR));
+        }
+        text.sections = sections;
+    }
+
+    for mut text in &mut input_query {
+        text.sections[0].value = match &chat.typing {
+            Some(typing) => format!("> {typing}_"),
+            None => "Enter to chat".to_owned(),
+        };
+    }
+}
+
+/// Adds the chat box to the app, it shows up once there's another player to chat with.
+pub fn build_chat(app: &mut App) {
+    app.init_resource::<Chat>()
+        .add_startup_system(spawn_chat_box)
+        .add_system(receive_chat)
+        .add_system(resend_chat.after(receive_chat))
+        .add_system(type_chat)
+        .add_system(update_chat_box.after(type_chat).after(receive_chat));
+}
@@ -1,24 +1,52 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Component)]
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
 pub struct Player {
     pub handle: usize
 }
 
-#[derive(Component)]
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
 pub struct Paddle;
 
-#[derive(Component)]
-pub struct Ball;
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
+pub struct Ball {
+    /// The player whose paddle touched the ball last, credited with the bricks it breaks
+    pub last_hit_by: Option<usize>,
+    /// Paddles and bricks the ball hit since it was served, it speeds up as they add up
+    pub hits: u32,
+}
 
-#[derive(Component, Default, Reflect, Deref, DerefMut)] 
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Deref, DerefMut)]
 pub struct Velocity(pub Vec2);
 
-#[derive(Component)]
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
 pub struct Collider;
 
-#[derive(Component, Default, Reflect)]
-pub struct CollisionEvent;
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
+pub struct Brick {
+    /// Whether it drops a power-up when broken
+    pub power_up: bool,
+    /// The player whose wall it's part of in versus, co-op walls belong to nobody
+    pub owner: Option<usize>,
+}
+
+/// Dropped by a broken brick, it falls until a paddle catches it or it falls past them.
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
+pub struct PowerUp {
+    /// The player it falls towards, the one who broke the brick in versus. Any paddle catches it in co-op
+    pub for_player: Option<usize>,
+}
+
+/// A paddle that caught a power-up and is wider for a while.
+#[derive(Component, Default, Reflect, FromReflect, Serialize, Deserialize, Hash)]
+pub struct Widened {
+    pub frames_left: u32,
+}
+
+#[derive(Component)]
+pub struct Wall;
 
+/// A line behind the paddles, balls crossing it are lost.
 #[derive(Component)]
-pub struct Brick;
\ No newline at end of file
+pub struct Goal;
@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+use rand::Rng;
+use std::f32::consts::TAU;
+use crate::components::Player;
+use crate::events::{confirm_events, ConfirmedEvent, SimulationEvent};
+use crate::game::{brick_color, player_color};
+
+
+// Effects
+// Particles, screen shake and paddle flashes, purely for show. They go off for confirmed events only, so a rollback
+// can neither set them off twice nor leave them behind for a brick that was never broken.
+// None of it is rolled back, and the simulation never reads any of it.
+
+const PARTICLES_PER_BRICK: usize = 12;
+const PARTICLE_SIZE: Vec3 = Vec3::new(6.0, 6.0, 1.0);
+const PARTICLE_MIN_SPEED: f32 = 60.0;
+const PARTICLE_MAX_SPEED: f32 = 240.0;
+// Seconds a particle lasts, fading out all the while
+const PARTICLE_LIFETIME: f32 = 0.6;
+// Over the bricks and paddles, under the ball
+const PARTICLE_Z: f32 = 0.5;
+
+// Every broken brick adds this much to the shake, a full 1 shakes the screen by `MAX_SHAKE_OFFSET`
+const BRICK_SHAKE: f32 = 0.3;
+// Shake lost per second
+const SHAKE_DECAY: f32 = 1.5;
+const MAX_SHAKE_OFFSET: f32 = 8.0;
+// How fast the screen shakes back and forth, in radians per second
+const SHAKE_FREQUENCY: f32 = 50.0;
+
+// Seconds a paddle lights up for when the ball bounces off it
+const FLASH_TIME: f32 = 0.15;
+const FLASH_COLOR: Color = Color::WHITE;
+
+
+/// A speck of a broken brick, flying off.
+#[derive(Component)]
+struct Particle {
+    velocity: Vec2,
+    /// Seconds since it was spawned
+    age: f32,
+}
+
+/// How much the screen is shaking, from 0 to 1.
+#[derive(Resource, Default)]
+struct ScreenShake(f32);
+
+/// Seconds each player's paddle stays lit up for, by handle.
+#[derive(Resource, Default)]
+struct PaddleFlashes([f32; 2]);
+
+fn react_to_events(
+    mut commands: Commands,
+    mut events: EventReader<ConfirmedEvent>,
+    mut shake: ResMut<ScreenShake>,
+    mut flashes: ResMut<PaddleFlashes>,
+) {
+    let mut rng = rand::thread_rng();
+    for ConfirmedEvent { event, .. } in events.iter() {
+        match event {
+            SimulationEvent::BrickDestroyed { position, owner, .. } => {
+                for _ in 0..PARTICLES_PER_BRICK {
+                    let direction = Vec2::from_angle(rng.gen_range(0.0..TAU));
+                    commands.spawn((
+                        SpriteBundle {
+                            transform: Transform::from_translation(position.extend(PARTICLE_Z)).with_scale(PARTICLE_SIZE),
+                            sprite: Sprite {
+                                color: brick_color(*owner),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        Particle {
+                            velocity: direction * rng.gen_range(PARTICLE_MIN_SPEED..PARTICLE_MAX_SPEED),
+                            age: 0.0,
+                        },
+                    ));
+                }
+                shake.0 = (shake.0 + BRICK_SHAKE).min(1.0);
+            }
+            SimulationEvent::PaddleHit { handle } => flashes.0[*handle] = FLASH_TIME,
+            SimulationEvent::WallHit
+            | SimulationEvent::BallLost { .. }
+            | SimulationEvent::PowerUpCollected { .. }
+            | SimulationEvent::LevelCleared { .. } => {}
+        }
+    }
+}
+
+fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite)>,
+) {
+    for (entity, mut particle, mut transform, mut sprite) in &mut query {
+        particle.age += time.delta_seconds();
+        if particle.age >= PARTICLE_LIFETIME {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        transform.translation += (particle.velocity * time.delta_seconds()).extend(0.0);
+        sprite.color.set_a(1.0 - particle.age / PARTICLE_LIFETIME);
+    }
+}
+
+/// Shakes the camera around the middle of the arena, harder the more bricks broke lately.
+fn shake_camera(time: Res<Time>, mut shake: ResMut<ScreenShake>, mut query: Query<&mut Transform, With<Camera2d>>) {
+    shake.0 = (shake.0 - SHAKE_DECAY * time.delta_seconds()).max(0.0);
+
+    // Shake feels right growing with the square of how much there is, slight shakes fade away smoothly
+    let angle = time.elapsed_seconds() * SHAKE_FREQUENCY;
+    let offset = Vec2::new(angle.sin(), (angle * 1.3).cos()) * shake.0 * shake.0 * MAX_SHAKE_OFFSET;
+    for mut transform in &mut query {
+        transform.translation.x = offset.x;
+        transform.translation.y = offset.y;
+    }
+}
+
+/// Lights paddles up when the ball bounces off them, fading back to their own color.
+fn flash_paddles(time: Res<Time>, mut flashes: ResMut<PaddleFlashes>, mut query: Query<(&Player, &mut Sprite)>) {
+    for flash in &mut flashes.0 {
+        *flash = (*flash - time.delta_seconds()).max(0.0);
+    }
+
+    for (player, mut sprite) in &mut query {
+        let lit = flashes.0[player.handle] / FLASH_TIME;
+        let own = Vec4::from(player_color(player.handle).as_rgba_f32());
+        let flash = Vec4::from(FLASH_COLOR.as_rgba_f32());
+        sprite.color = Color::from(own.lerp(flash, lit));
+    }
+}
+
+/// Adds the effects to the app.
+pub fn build_effects(app: &mut App) {
+    app.init_resource::<ScreenShake>()
+        .init_resource::<PaddleFlashes>()
+        .add_system(react_to_events.after(confirm_events))
+        .add_system(update_particles.after(react_to_events))
+        .add_system(shake_camera.after(react_to_events))
+        .add_system(flash_paddles.after(react_to_events));
+}
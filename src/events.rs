@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs::PlayerHandle, Session};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::netcode::{FrameCount, GgrsConfig};
+
+
+// Events
+// What happens in the simulation, for effects, sounds, stats and replays to react to.
+// Events only get out once their frame is confirmed: until then a rollback may play the frame out differently,
+// and reacting to predictions would count things twice, or things that never happened.
+// Confirmed events lag behind the simulation by the frames GGRS predicts ahead, and come out in the order they happened.
+// What can't wait that long, like sounds, hears of events as soon as a frame plays them out, as predicted events.
+// Each is announced once, however often rollbacks play its frame out again, but a rollback may take it back after all.
+
+/// Something that happened in the simulation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SimulationEvent {
+    /// The brick with rollback id `id` was broken at `position`, out of `owner`'s wall in versus.
+    /// `by_player` is credited with it, if anyone is.
+    BrickDestroyed {
+        id: u32,
+        by_player: Option<PlayerHandle>,
+        owner: Option<PlayerHandle>,
+        position: Vec2,
+    },
+    /// The ball bounced off a player's paddle.
+    PaddleHit { handle: PlayerHandle },
+    /// The ball bounced off one of the walls around the arena.
+    WallHit,
+    /// The ball got into a goal, `defender`'s in versus and the team's in co-op.
+    BallLost { defender: Option<PlayerHandle> },
+    /// A player's paddle caught a power-up.
+    PowerUpCollected { handle: PlayerHandle },
+    /// The team cleared the wall in co-op, and the ball is served anew on `level`.
+    LevelCleared { level: u32 },
+}
+
+/// The events of the frame being advanced, for the simulation's systems to send.
+#[derive(Resource, Default)]
+pub struct FrameEvents(Vec<SimulationEvent>);
+
+impl FrameEvents {
+    pub fn send(&mut self, event: SimulationEvent) {
+        self.0.push(event);
+    }
+}
+
+/// Events of frames that may still be rolled back, by the frame they happened on. Resimulating a frame replaces its events.
+#[derive(Resource, Default)]
+pub struct EventLog(BTreeMap<u32, Vec<SimulationEvent>>);
+
+/// An event whose frame is confirmed, it happened for sure.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfirmedEvent {
+    pub frame: u32,
+    pub event: SimulationEvent,
+}
+
+/// An event of a frame that may still be rolled back, announced the first time the frame plays it out.
+#[derive(Clone, Debug)]
+pub struct PredictedEvent {
+    pub frame: u32,
+    pub event: SimulationEvent,
+}
+
+/// The events announced so far of frames not confirmed yet, by frame.
+#[derive(Resource, Default)]
+pub struct AnnouncedEvents(BTreeMap<u32, Vec<SimulationEvent>>);
+
+/// Files the events of the frame being advanced under that frame. Runs last in the rollback schedule.
+pub fn log_frame_events(frame_count: Res<FrameCount>, mut events: ResMut<FrameEvents>, mut log: ResMut<EventLog>) {
+    log.0.insert(frame_count.frame, std::mem::take(&mut events.0));
+}
+
+/// The last frame no rollback can go back on, counted like `FrameCount`. Negative before the first one is.
+pub fn confirmed_frame(session: &Session<GgrsConfig>, frame_count: &FrameCount) -> i64 {
+    let unconfirmed = match session {
+        // A resumed match counts its frames on from where it was, while its session counts from zero
+        Session::P2PSession(session) => (session.current_frame() - session.confirmed_frame()).max(1),
+        // A synctest may roll back as far as it predicts
+        Session::SyncTestSession(session) => session.max_prediction() as i32 + 1,
+        // Spectators only ever advance confirmed frames
+        Session::SpectatorSession(_) => 1,
+    };
+    frame_count.frame as i64 - unconfirmed as i64
+}
+
+/// Announces the events of unconfirmed frames that weren't announced yet, in the order they happened.
+/// Events that rollbacks played out and took back within the same update are never announced.
+pub fn announce_events(
+    log: Res<EventLog>,
+    mut announced: ResMut<AnnouncedEvents>,
+    mut predicted: EventWriter<PredictedEvent>,
+) {
+    announced.0.retain(|frame, _| log.0.contains_key(frame));
+    for (frame, events) in &log.0 {
+        let announced = announced.0.entry(*frame).or_default();
+        let mut unmatched: Vec<&SimulationEvent> = announced.iter().collect();
+        let new: Vec<SimulationEvent> = events
+            .iter()
+            .filter(|event| match unmatched.iter().position(|seen| seen == event) {
+                Some(index) => {
+                    unmatched.swap_remove(index);
+                    false
+                }
+                None => true,
+            })
+            .cloned()
+            .collect();
+
+        predicted.send_batch(new.iter().map(|event| PredictedEvent { frame: *frame, event: event.clone() }));
+        announced.extend(new);
+    }
+}
+
+/// Sends the events of frames that got confirmed since the last update, in the order they happened.
+pub fn confirm_events(
+    session: Option<Res<Session<GgrsConfig>>>,
+    frame_count: Res<FrameCount>,
+    mut log: ResMut<EventLog>,
+    mut confirmed: EventWriter<ConfirmedEvent>,
+) {
+    let Some(session) = session else {
+        return;
+    };
+
+    let confirmed_frame = confirmed_frame(&session, &frame_count);
+    while log.0.first_key_value().is_some_and(|(frame, _)| *frame as i64 <= confirmed_frame) {
+        let (frame, events) = log.0.pop_first().expect("The log isn't empty");
+        confirmed.send_batch(events.into_iter().map(|event| ConfirmedEvent { frame, event }));
+    }
+}
+
+/// Forgets the events of frames not confirmed yet, for when the simulation starts over.
+pub fn clear_events(world: &mut World) {
+    world.insert_resource(FrameEvents::default());
+    world.insert_resource(EventLog::default());
+    world.insert_resource(AnnouncedEvents::default());
+}
+
+/// Adds simulation events to the app. `log_frame_events` has to run in the rollback schedule, after every system sending them.
+pub fn build_events(app: &mut App) {
+    app.init_resource::<FrameEvents>()
+        .init_resource::<EventLog>()
+        .init_resource::<AnnouncedEvents>()
+        .add_event::<PredictedEvent>()
+        .add_event::<ConfirmedEvent>()
+        .add_system(announce_events)
+        .add_system(confirm_events.after(announce_events));
+}
@@ -0,0 +1,344 @@
+use bevy::prelude::*;
+use bevy_ggrs::ggrs::PlayerHandle;
+use serde::{Deserialize, Serialize};
+use crate::ai::Bots;
+use crate::game::AppState;
+use crate::netcode::{LocalPlayerHandle, RemotePeer};
+use crate::series::{player_name, MatchOver};
+use crate::simulation::GameMode;
+use crate::stats::MatchStats;
+use crate::ui::*;
+
+
+// History
+// Finished matches are kept on the device they were played on, natively in a file in the user's data directory
+// and on the web in the browser's local storage, so that they outlast the game.
+// The main menu shows them two ways: the best scores on the leaderboard, and the latest matches in the history.
+
+#[cfg(not(target_arch = "wasm32"))]
+const DATA_DIR: &str = "bifrost";
+#[cfg(not(target_arch = "wasm32"))]
+const HISTORY_FILE: &str = "bifrost-history.json";
+#[cfg(target_arch = "wasm32")]
+const HISTORY_KEY: &str = "bifrost-history";
+// Older matches are forgotten beyond this, to keep the history quick to load and store
+pub const MAX_RECORDS: usize = 100;
+// Matches listed on the leaderboard and history screens
+const SHOWN_RECORDS: usize = 10;
+const BACK_KEY: KeyCode = KeyCode::Escape;
+
+const RECORD_FONT_SIZE: f32 = 20.0;
+const HEADER_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+const LEADERBOARD_WIDTHS: [f32; 6] = [40.0, 90.0, 300.0, 80.0, 70.0, 130.0];
+const HISTORY_WIDTHS: [f32; 5] = [130.0, 90.0, 300.0, 260.0, 80.0];
+
+
+/// A finished match, as it's remembered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub mode: GameMode,
+    /// Who played, by handle
+    pub players: [String; 2],
+    /// Who won the series, nobody in co-op
+    pub winner: Option<PlayerHandle>,
+    /// Rounds won by each player, by handle
+    pub wins: [u32; 2],
+    /// Points scored by each player, by handle
+    pub points: [usize; 2],
+    /// The team's score in co-op
+    pub team_score: Option<usize>,
+    /// The level the match ended on
+    pub level: u32,
+    /// Seconds the rounds were in play
+    pub duration: f32,
+    /// When the match ended, in seconds since the Unix epoch
+    pub finished_at: u64,
+}
+
+impl MatchRecord {
+    /// The score the match ranks by: the team's in co-op, and the winner's points in versus.
+    pub fn score(&self) -> usize {
+        match (self.team_score, self.winner) {
+            (Some(score), _) => score,
+            (None, Some(winner)) => self.points[winner],
+            (None, None) => self.points.into_iter().max().unwrap_or(0),
+        }
+    }
+
+    /// Who made the score: both players in co-op, the winner in versus.
+    fn scored_by(&self) -> String {
+        match (self.mode, self.winner) {
+            (GameMode::Versus, Some(winner)) => self.players[winner].clone(),
+            _ => self.players.join(" & "),
+        }
+    }
+
+    fn result(&self) -> String {
+        match (self.team_score, self.winner) {
+            (Some(score), _) => format!("{} on level {}", score, self.level),
+            (None, Some(winner)) => format!("{} - {}, {} won", self.wins[0], self.wins[1], player_name(winner)),
+            (None, None) => format!("{} - {}", self.wins[0], self.wins[1]),
+        }
+    }
+}
+
+/// The matches played on this device, oldest first.
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct MatchHistory {
+    pub records: Vec<MatchRecord>,
+}
+
+impl MatchHistory {
+    /// Reads the history stored by earlier games, starting a new one if there's none or it can't be read.
+    pub fn load() -> MatchHistory {
+        let Some(json) = read_stored() else {
+            return MatchHistory::default();
+        };
+        serde_json::from_str(&json).unwrap_or_else(|err| {
+            warn!("Failed to read the match history, starting a new one: {}", err);
+            MatchHistory::default()
+        })
+    }
+
+    fn store(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => write_stored(&json),
+            Err(err) => error!("Failed to store the match history: {}", err),
+        }
+    }
+
+    /// Remembers a finished match, forgetting the oldest beyond `MAX_RECORDS`.
+    pub fn add(&mut self, record: MatchRecord) {
+        self.records.push(record);
+        let excess = self.records.len().saturating_sub(MAX_RECORDS);
+        self.records.drain(..excess);
+    }
+
+    /// The matches with the best scores, best first. Of equal scores the latest comes first.
+    pub fn leaderboard(&self) -> Vec<&MatchRecord> {
+        let mut records: Vec<&MatchRecord> = self.records.iter().rev().collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.score()));
+        records
+    }
+}
+
+/// Where the history is stored natively: in the game's directory under the user's data directory,
+/// or next to the game if the user has none.
+#[cfg(not(target_arch = "wasm32"))]
+fn history_path() -> std::path::PathBuf {
+    use std::path::PathBuf;
+
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let data_dir = match cfg!(windows) {
+        true => var("APPDATA"),
+        false => var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local").join("share"))),
+    };
+    match data_dir {
+        Some(data_dir) => data_dir.join(DATA_DIR).join(HISTORY_FILE),
+        None => PathBuf::from(HISTORY_FILE),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stored() -> Option<String> {
+    std::fs::read_to_string(history_path()).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_stored(json: &str) {
+    let path = history_path();
+    let created = path.parent().map_or(Ok(()), std::fs::create_dir_all);
+    if let Err(err) = created.and_then(|()| std::fs::write(&path, json)) {
+        warn!("Failed to write {}: {}", path.display(), err);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_stored() -> Option<String> {
+    local_storage()?.get_item(HISTORY_KEY).ok().flatten()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_stored(json: &str) {
+    let stored = local_storage().is_some_and(|storage| storage.set_item(HISTORY_KEY, json).is_ok());
+    if !stored {
+        warn!("Failed to store the match history, local storage isn't available");
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    #[cfg(target_arch = "wasm32")]
+    return (js_sys::Date::now() / 1000.0) as u64;
+}
+
+/// How long ago something happened, roughly.
+fn ago(then: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(then);
+    match seconds {
+        0..=59 => "just now".to_owned(),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86399 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// Remembers the match once it's over, with who played it.
+fn record_match(
+    over: Option<Res<MatchOver>>,
+    stats: Res<MatchStats>,
+    bots: Res<Bots>,
+    peer: Option<Res<RemotePeer>>,
+    local_handle: Option<Res<LocalPlayerHandle>>,
+    mut history: ResMut<MatchHistory>,
+) {
+    let Some(over) = over.filter(|over| over.is_added()) else {
+        return;
+    };
+
+    let name = |handle| match (bots.0.contains_key(&handle), &local_handle, &peer) {
+        (true, _, _) => format!("{} (AI)", player_name(handle)),
+        (false, Some(local_handle), Some(_)) if local_handle.0 == handle => format!("{} (you)", player_name(handle)),
+        (false, Some(_), Some(_)) => format!("{} (online)", player_name(handle)),
+        _ => player_name(handle),
+    };
+    history.add(MatchRecord {
+        mode: over.mode,
+        players: [name(0), name(1)],
+        winner: over.winner,
+        wins: over.wins,
+        points: over.points,
+        team_score: over.team_score,
+        level: over.level,
+        duration: stats.duration,
+        finished_at: unix_time(),
+    });
+    history.store();
+}
+
+/// Everything on the leaderboard and history screens, despawned when leaving them.
+#[derive(Component)]
+struct RecordsUi;
+
+#[derive(Component)]
+struct BackButton;
+
+/// Spawns a table of `rows` under a row of `headers`, their columns `widths` wide.
+fn spawn_table(parent: &mut ChildBuilder, font: &Handle<Font>, widths: &[f32], headers: &[&str], rows: Vec<Vec<String>>) {
+    let spawn_row = |parent: &mut ChildBuilder, cells: Vec<String>, color: Color| {
+        parent.spawn(row()).with_children(|parent| {
+            for (cell, width) in cells.into_iter().zip(widths) {
+                parent.spawn(ui_text(cell, font, RECORD_FONT_SIZE, color).with_style(Style {
+                    size: Size::new(Val::Px(*width), Val::Auto),
+                    ..default()
+                }));
+            }
+        });
+    };
+
+    parent.spawn(column()).with_children(|parent| {
+        if rows.is_empty() {
+            parent.spawn(ui_text("No matches played yet", font, UI_FONT_SIZE, HEADER_COLOR));
+            return;
+        }
+        spawn_row(parent, headers.iter().map(|header| header.to_string()).collect(), HEADER_COLOR);
+        for cells in rows {
+            spawn_row(parent, cells, UI_TEXT_COLOR);
+        }
+    });
+}
+
+fn spawn_leaderboard(mut commands: Commands, asset_server: Res<AssetServer>, history: Res<MatchHistory>) {
+    let font = ui_font(&asset_server);
+    let now = unix_time();
+    let rows = history
+        .leaderboard()
+        .into_iter()
+        .take(SHOWN_RECORDS)
+        .enumerate()
+        .map(|(rank, record)| {
+            vec![
+                format!("{}.", rank + 1),
+                record.mode.name().to_owned(),
+                record.scored_by(),
+                record.score().to_string(),
+                format!("Lv {}", record.level),
+                ago(record.finished_at, now),
+            ]
+        })
+        .collect();
+
+    commands.spawn((screen(), RecordsUi)).with_children(|parent| {
+        parent.spawn(ui_text("Leaderboard", &font, TITLE_FONT_SIZE, UI_TEXT_COLOR));
+        let headers = ["", "Mode", "Scored by", "Score", "Level", "When"];
+        spawn_table(parent, &font, &LEADERBOARD_WIDTHS, &headers, rows);
+        spawn_button(parent, &font, "Back", BUTTON_SIZE, BackButton);
+    });
+}
+
+fn spawn_history(mut commands: Commands, asset_server: Res<AssetServer>, history: Res<MatchHistory>) {
+    let font = ui_font(&asset_server);
+    let now = unix_time();
+    let rows = history
+        .records
+        .iter()
+        .rev()
+        .take(SHOWN_RECORDS)
+        .map(|record| {
+            let seconds = record.duration as u32;
+            vec![
+                ago(record.finished_at, now),
+                record.mode.name().to_owned(),
+                record.players.join(" vs "),
+                record.result(),
+                format!("{}:{:02}", seconds / 60, seconds % 60),
+            ]
+        })
+        .collect();
+
+    commands.spawn((screen(), RecordsUi)).with_children(|parent| {
+        parent.spawn(ui_text("Match History", &font, TITLE_FONT_SIZE, UI_TEXT_COLOR));
+        let headers = ["When", "Mode", "Players", "Result", "Time"];
+        spawn_table(parent, &font, &HISTORY_WIDTHS, &headers, rows);
+        spawn_button(parent, &font, "Back", BUTTON_SIZE, BackButton);
+    });
+}
+
+fn despawn_records(mut commands: Commands, query: Query<Entity, With<RecordsUi>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Back goes back to the main menu, as does Escape.
+fn leave_records(
+    keys: Res<Input<KeyCode>>,
+    query: Query<&Interaction, (Changed<Interaction>, With<BackButton>)>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(BACK_KEY) || query.iter().any(|interaction| *interaction == Interaction::Clicked) {
+        state.set(AppState::Menu).ok();
+    }
+}
+
+/// Adds the match history to the app, loaded from where earlier games stored it,
+/// with the leaderboard and history screens shown in `AppState::Leaderboard` and `AppState::History`.
+pub fn build_history(app: &mut App) {
+    app.insert_resource(MatchHistory::load())
+        .add_system(record_match)
+        .add_system_set(SystemSet::on_enter(AppState::Leaderboard).with_system(spawn_leaderboard))
+        .add_system_set(SystemSet::on_update(AppState::Leaderboard).with_system(leave_records))
+        .add_system_set(SystemSet::on_exit(AppState::Leaderboard).with_system(despawn_records))
+        .add_system_set(SystemSet::on_enter(AppState::History).with_system(spawn_history))
+        .add_system_set(SystemSet::on_update(AppState::History).with_system(leave_records))
+        .add_system_set(SystemSet::on_exit(AppState::History).with_system(despawn_records));
+}
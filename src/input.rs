@@ -1,10 +1,13 @@
 use bevy::prelude::*;
 use bevy_ggrs::ggrs::PlayerHandle;
 use bitflags::bitflags;
+use crate::ai::BotInput;
+use crate::chat::Chat;
+use crate::netcode::LocalPlayerHandle;
 
 
 bitflags! {
-    struct  PlayerInput: u8 {
+    pub struct PlayerInput: u8 {
         const UP = 1 << 0;
         const DOWN = 1 << 1;
         const LEFT = 1 << 2;
@@ -12,24 +15,106 @@ bitflags! {
     }
 }
 
+// Key bindings, ordered up, down, left, right
+const WASD_KEYS: [KeyCode; 4] = [KeyCode::W, KeyCode::S, KeyCode::A, KeyCode::D];
+const ARROW_KEYS: [KeyCode; 4] = [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right];
+const IJKL_KEYS: [KeyCode; 4] = [KeyCode::I, KeyCode::K, KeyCode::J, KeyCode::L];
+const NUMPAD_KEYS: [KeyCode; 4] = [KeyCode::Numpad8, KeyCode::Numpad5, KeyCode::Numpad4, KeyCode::Numpad6];
+
+/// The keys a player moves their paddle with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySet {
+    Wasd,
+    Arrows,
+    Ijkl,
+    Numpad,
+}
+
+impl KeySet {
+    const ALL: [KeySet; 4] = [KeySet::Wasd, KeySet::Arrows, KeySet::Ijkl, KeySet::Numpad];
+
+    fn keys(self) -> [KeyCode; 4] {
+        match self {
+            KeySet::Wasd => WASD_KEYS,
+            KeySet::Arrows => ARROW_KEYS,
+            KeySet::Ijkl => IJKL_KEYS,
+            KeySet::Numpad => NUMPAD_KEYS,
+        }
+    }
+
+    /// The key set `steps` further along the list of them, wrapping around.
+    pub fn cycle(self, steps: i32) -> KeySet {
+        let index = KeySet::ALL.iter().position(|set| *set == self).unwrap_or_default() as i32;
+        KeySet::ALL[(index + steps).rem_euclid(KeySet::ALL.len() as i32) as usize]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeySet::Wasd => "WASD",
+            KeySet::Arrows => "Arrows",
+            KeySet::Ijkl => "IJKL",
+            KeySet::Numpad => "Numpad",
+        }
+    }
+}
+
+/// Which keys each player uses, by handle. Playing online, our player answers to both sets.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct Controls {
+    pub players: [KeySet; 2],
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Controls {
+            players: [KeySet::Wasd, KeySet::Arrows],
+        }
+    }
+}
+
 /// Handles player input
-pub fn input(_: In<PlayerHandle>, keys: Res<Input<KeyCode>>) -> u8 {
+pub fn input(
+    handle: In<PlayerHandle>,
+    keys: Res<Input<KeyCode>>,
+    controls: Res<Controls>,
+    local_handle: Option<Res<LocalPlayerHandle>>,
+    chat: Option<Res<Chat>>,
+    mut bot_input: BotInput,
+) -> u8 {
+    if let Some(input) = bot_input.input(handle.0) {
+        return input;
+    }
+    // Whatever is typed into the chat box isn't meant for the paddle
+    if chat.is_some_and(|chat| chat.is_typing()) {
+        return 0;
+    }
+
+    let [p1_keys, p2_keys] = controls.players.map(KeySet::keys);
+    let bindings: &[[KeyCode; 4]] = match local_handle {
+        // Playing online, the whole keyboard belongs to our player
+        Some(local_handle) if local_handle.0 == handle.0 => &[p1_keys, p2_keys],
+        // Playing locally, the keyboard is split between both players
+        _ if handle.0 == 0 => &[p1_keys],
+        _ => &[p2_keys],
+    };
+    let pressed = |i: usize| keys.any_pressed(bindings.iter().map(|keys| keys[i]));
+
     let mut input = 0u8;
 
-    if keys.any_pressed([KeyCode::Up, KeyCode::W]) {
+    if pressed(0) {
         input |= PlayerInput::UP.bits;
     }
-    if keys.any_pressed([KeyCode::Down, KeyCode::S]) {
+    if pressed(1) {
         input |= PlayerInput::DOWN.bits;
     }
-    if keys.any_pressed([KeyCode::Left, KeyCode::A]) {
+    if pressed(2) {
         input |= PlayerInput::LEFT.bits;
     }
-    if keys.any_pressed([KeyCode::Right, KeyCode::D]) {
+    if pressed(3) {
         input |= PlayerInput::RIGHT.bits;
     }
 
-    return input;
+    input
 }
 
 pub fn direction(input: u8) -> Vec2 {
@@ -48,5 +133,5 @@ pub fn direction(input: u8) -> Vec2 {
         direction.x += 1.0;
     }
 
-    return direction;
-}
\ No newline at end of file
+    direction
+}
@@ -0,0 +1,182 @@
+use bevy::{
+    prelude::*,
+    utils::{Duration, Instant},
+};
+use crate::channels::{ControlEvent, ControlMessage, SharedSocket};
+use crate::simulation::FrameRate;
+
+
+// Latency
+// Before a match starts, the peers ping each other to measure their round trip time and pick an input delay from it.
+// Delaying inputs hides latency at the cost of responsiveness, rolling back hides it at the cost of corrections,
+// so a close peer gets a short delay and a distant one a longer delay with fewer rollbacks.
+
+// Round trip times measured before settling on one
+const PROBE_SAMPLES: usize = 10;
+const PROBE_INTERVAL: Duration = Duration::from_millis(50);
+// Pings sent without hearing back are forgotten after this many more have been sent
+const MAX_PENDING_PINGS: usize = 16;
+
+
+/// Bounds of the input delay, in frames.
+/// Both peers should use the same bounds, otherwise each delays its own inputs by its own pick.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputDelaySettings {
+    pub min: usize,
+    pub max: usize,
+    /// Whether every round measures the latency again, rather than keeping the delay picked for the first one.
+    pub renegotiate: bool,
+}
+
+impl Default for InputDelaySettings {
+    fn default() -> Self {
+        InputDelaySettings {
+            min: 1,
+            max: 6,
+            renegotiate: false,
+        }
+    }
+}
+
+impl InputDelaySettings {
+    /// The input delay covering the time inputs take to reach the other peer, within bounds.
+    pub fn delay_for_rtt(&self, rtt_ms: u32, frame_rate: FrameRate) -> usize {
+        let frame_ms = 1000.0 * frame_rate.time_step();
+        let one_way_frames = (rtt_ms as f32 / 2.0 / frame_ms).round() as usize;
+        one_way_frames.clamp(self.min, self.max.max(self.min))
+    }
+}
+
+/// Measures the round trip time to the other peer and agrees on an input delay with them.
+#[derive(Resource)]
+pub struct DelayNegotiation {
+    peer: String,
+    /// Counts the times the latency was measured, round trip times from another measurement are ignored
+    measurement: u32,
+    next_seq: u32,
+    /// When the pings still waiting for an answer were sent, indexed by their `seq` modulo the length
+    pending: [Option<(u32, Instant)>; MAX_PENDING_PINGS],
+    last_ping: Option<Instant>,
+    samples: Vec<Duration>,
+    local_rtt_ms: Option<u32>,
+    remote_rtt_ms: Option<u32>,
+}
+
+impl DelayNegotiation {
+    pub fn new(peer: String) -> DelayNegotiation {
+        DelayNegotiation {
+            peer,
+            measurement: 0,
+            next_seq: 0,
+            pending: [None; MAX_PENDING_PINGS],
+            last_ping: None,
+            samples: Vec::new(),
+            local_rtt_ms: None,
+            remote_rtt_ms: None,
+        }
+    }
+
+    /// Forgets the measurements so far, to measure the latency again.
+    /// Both peers have to restart, after which they only agree on round trip times they measured anew.
+    pub fn restart(&mut self) {
+        let measurement = self.measurement + 1;
+        // Pings keep counting up, so a late pong can't pass for the answer to a new ping
+        let next_seq = self.next_seq;
+        *self = DelayNegotiation::new(std::mem::take(&mut self.peer));
+        self.measurement = measurement;
+        self.next_seq = next_seq;
+    }
+
+    fn record_remote_rtt(&mut self, measurement: u32, rtt_ms: Option<u32>) {
+        if measurement == self.measurement {
+            self.remote_rtt_ms = self.remote_rtt_ms.or(rtt_ms);
+        }
+    }
+
+    /// The round trip time both peers agree on, the longer of the two they measured.
+    pub fn rtt_ms(&self) -> Option<u32> {
+        Some(self.local_rtt_ms?.max(self.remote_rtt_ms?))
+    }
+
+    /// The input delay both peers agree on, once they've both measured their round trip time.
+    pub fn input_delay(&self, settings: &InputDelaySettings, frame_rate: FrameRate) -> Option<usize> {
+        Some(settings.delay_for_rtt(self.rtt_ms()?, frame_rate))
+    }
+
+    fn record_pong(&mut self, seq: u32) {
+        let slot = &mut self.pending[seq as usize % MAX_PENDING_PINGS];
+        let Some((sent_seq, sent_at)) = *slot else {
+            return;
+        };
+        if sent_seq != seq {
+            return;
+        }
+        *slot = None;
+
+        if self.local_rtt_ms.is_none() {
+            self.samples.push(sent_at.elapsed());
+            if self.samples.len() == PROBE_SAMPLES {
+                // The median shrugs off the odd packet stuck in a queue
+                self.samples.sort();
+                self.local_rtt_ms = Some(self.samples[PROBE_SAMPLES / 2].as_millis() as u32);
+                info!("Measured a round trip time of {} ms", self.local_rtt_ms.unwrap());
+            }
+        }
+    }
+}
+
+/// Pings the other peer until both know the round trip time, and answers their pings.
+/// Keeps answering after the session has started, the other peer may still be waiting to hear our round trip time.
+pub fn negotiate_input_delay(
+    socket: Option<Res<SharedSocket>>,
+    negotiation: Option<ResMut<DelayNegotiation>>,
+    mut events: EventReader<ControlEvent>,
+) {
+    let (Some(socket), Some(mut negotiation)) = (socket, negotiation) else {
+        return;
+    };
+
+    for event in events.iter() {
+        if event.peer != negotiation.peer {
+            continue;
+        }
+
+        match event.message {
+            ControlMessage::Ping { seq, measurement, rtt_ms } => {
+                negotiation.record_remote_rtt(measurement, rtt_ms);
+                let pong = ControlMessage::Pong {
+                    seq,
+                    measurement: negotiation.measurement,
+                    rtt_ms: negotiation.local_rtt_ms,
+                };
+                socket.send_control(&pong, &event.peer);
+            }
+            ControlMessage::Pong { seq, measurement, rtt_ms } => {
+                negotiation.record_remote_rtt(measurement, rtt_ms);
+                negotiation.record_pong(seq);
+            }
+            _ => {}
+        }
+    }
+
+    let done = negotiation.rtt_ms().is_some();
+    let due = negotiation
+        .last_ping
+        .map_or(true, |last_ping| last_ping.elapsed() >= PROBE_INTERVAL);
+    if done || !due {
+        return;
+    }
+
+    let seq = negotiation.next_seq;
+    let now = Instant::now();
+    negotiation.next_seq += 1;
+    negotiation.last_ping = Some(now);
+    negotiation.pending[seq as usize % MAX_PENDING_PINGS] = Some((seq, now));
+
+    let ping = ControlMessage::Ping {
+        seq,
+        measurement: negotiation.measurement,
+        rtt_ms: negotiation.local_rtt_ms,
+    };
+    socket.send_control(&ping, &negotiation.peer);
+}
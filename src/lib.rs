@@ -0,0 +1,31 @@
+//! Bifrost, a game of Breakout exploring rollback P2P sessions.
+//!
+//! The deterministic game rules live in [`simulation`] and run without a renderer,
+//! so they can be driven headless by tests, bots and benchmarks.
+//! [`game`] puts a face on them for players.
+
+pub mod ai;
+pub mod channels;
+pub mod chat;
+pub mod components;
+pub mod effects;
+pub mod events;
+pub mod game;
+pub mod history;
+pub mod input;
+pub mod latency;
+pub mod lobby;
+pub mod menu;
+pub mod netcode;
+pub mod overlay;
+pub mod random;
+pub mod reconnect;
+pub mod rollback;
+pub mod savestate;
+pub mod series;
+pub mod settings;
+pub mod simulation;
+pub mod smoothing;
+pub mod snapshot;
+pub mod stats;
+pub mod ui;
@@ -0,0 +1,232 @@
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use crate::channels::SharedSocket;
+use crate::game::AppState;
+use crate::latency::DelayNegotiation;
+use crate::netcode::*;
+use crate::reconnect::Greeting;
+use crate::ui::*;
+
+
+// Lobby
+// Where online players pick who to play with: a stranger from quickmatch, or a friend in a private room.
+// A private room has a short code that the friend types in to join.
+// Quickmatch pairs players wanting the same mode, a friend joining a room plays the mode it was made for.
+
+const LOBBY_CODE_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
+
+
+/// Everything on the lobby screen, despawned when leaving it.
+#[derive(Component)]
+struct LobbyUi;
+
+/// What a lobby button does when clicked.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum LobbyButton {
+    Quickmatch,
+    CreateRoom,
+    JoinRoom,
+    SwitchMode,
+    Cancel,
+    /// Back to the main menu
+    Back,
+}
+
+/// The buttons and code field for picking a room, hidden once we're waiting in one.
+#[derive(Component)]
+struct RoomChoices;
+
+/// Shown while waiting in a room, with a way back out.
+#[derive(Component)]
+struct RoomWaiting;
+
+#[derive(Component)]
+struct StatusText;
+
+#[derive(Component)]
+struct CodeField;
+
+#[derive(Component)]
+struct ModeText;
+
+/// The room code typed in so far.
+#[derive(Resource, Default)]
+struct TypedCode(String);
+
+fn spawn_lobby(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = ui_font(&asset_server);
+    let mono_font = asset_server.load("../assets/fonts/FiraMono-Medium.ttf");
+
+    commands.insert_resource(TypedCode::default());
+    commands
+        .spawn((screen(), LobbyUi))
+        .with_children(|parent| {
+            parent.spawn(ui_text("Bifrost", &font, TITLE_FONT_SIZE, UI_TEXT_COLOR));
+            parent.spawn((ui_text("", &font, UI_FONT_SIZE, UI_TEXT_COLOR), StatusText));
+
+            parent.spawn((column(), RoomChoices)).with_children(|parent| {
+                parent.spawn(row()).with_children(|parent| {
+                    parent.spawn((ui_text("", &font, UI_FONT_SIZE, UI_TEXT_COLOR), ModeText));
+                    spawn_button(parent, &font, "Switch", BUTTON_SIZE, LobbyButton::SwitchMode);
+                });
+                parent.spawn(row()).with_children(|parent| {
+                    spawn_button(parent, &font, "Quickmatch", BUTTON_SIZE, LobbyButton::Quickmatch);
+                    spawn_button(parent, &font, "Create Room", BUTTON_SIZE, LobbyButton::CreateRoom);
+                });
+                parent.spawn(row()).with_children(|parent| {
+                    parent.spawn((ui_text("", &mono_font, UI_FONT_SIZE, LOBBY_CODE_COLOR), CodeField));
+                    spawn_button(parent, &font, "Join Room", BUTTON_SIZE, LobbyButton::JoinRoom);
+                });
+                spawn_button(parent, &font, "Back", BUTTON_SIZE, LobbyButton::Back);
+            });
+
+            parent.spawn((column(), RoomWaiting)).with_children(|parent| {
+                spawn_button(parent, &font, "Cancel", BUTTON_SIZE, LobbyButton::Cancel);
+            });
+        });
+}
+
+fn despawn_lobby(mut commands: Commands, query: Query<Entity, With<LobbyUi>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<TypedCode>();
+}
+
+/// Stops looking for a match, dropping the socket along with everything that was going on over it.
+fn leave_room(commands: &mut Commands) {
+    commands.remove_resource::<Matchmaking>();
+    commands.remove_resource::<P2PSession>();
+    commands.remove_resource::<SharedSocket>();
+    commands.remove_resource::<RemotePeer>();
+    commands.remove_resource::<DelayNegotiation>();
+    commands.remove_resource::<SessionPlayers>();
+    commands.remove_resource::<Greeting>();
+}
+
+fn click_lobby_buttons(
+    mut commands: Commands,
+    typed_code: Res<TypedCode>,
+    mut mode_choice: ResMut<ModeChoice>,
+    mut state: ResMut<State<AppState>>,
+    query: Query<(&Interaction, &LobbyButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            LobbyButton::Quickmatch => {
+                mode_choice.proposes = true;
+                commands.insert_resource(Matchmaking::Quickmatch);
+            }
+            LobbyButton::CreateRoom => {
+                mode_choice.proposes = true;
+                commands.insert_resource(Matchmaking::new_private());
+            }
+            LobbyButton::JoinRoom if typed_code.0.len() == ROOM_CODE_LENGTH => {
+                mode_choice.proposes = false;
+                commands.insert_resource(Matchmaking::Private(typed_code.0.clone()));
+            }
+            LobbyButton::JoinRoom => {}
+            LobbyButton::SwitchMode => mode_choice.mode = mode_choice.mode.other(),
+            LobbyButton::Cancel => leave_room(&mut commands),
+            LobbyButton::Back => {
+                leave_room(&mut commands);
+                state.set(AppState::Menu).ok();
+            }
+        }
+    }
+}
+
+/// Types the room code to join, Enter joins right away.
+fn type_room_code(
+    mut commands: Commands,
+    mut characters: EventReader<ReceivedCharacter>,
+    keys: Res<Input<KeyCode>>,
+    matchmaking: Option<Res<Matchmaking>>,
+    mut typed_code: ResMut<TypedCode>,
+    mut mode_choice: ResMut<ModeChoice>,
+) {
+    if matchmaking.is_some() {
+        characters.clear();
+        return;
+    }
+
+    for character in characters.iter() {
+        let character = character.char.to_ascii_uppercase();
+        if ROOM_CODE_CHARACTERS.contains(character) && typed_code.0.len() < ROOM_CODE_LENGTH {
+            typed_code.0.push(character);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        typed_code.0.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) && typed_code.0.len() == ROOM_CODE_LENGTH {
+        mode_choice.proposes = false;
+        commands.insert_resource(Matchmaking::Private(typed_code.0.clone()));
+    }
+}
+
+fn update_lobby(
+    matchmaking: Option<Res<Matchmaking>>,
+    players: Option<Res<SessionPlayers>>,
+    typed_code: Res<TypedCode>,
+    mut status_query: Query<&mut Text, (With<StatusText>, Without<CodeField>)>,
+    mut code_query: Query<&mut Text, (With<CodeField>, Without<StatusText>)>,
+    mut choices_query: Query<&mut Style, (With<RoomChoices>, Without<RoomWaiting>)>,
+    mut waiting_query: Query<&mut Style, (With<RoomWaiting>, Without<RoomChoices>)>,
+) {
+    let status = match (matchmaking.as_deref(), players) {
+        (None, _) => "Play a stranger, or make a room for a friend".to_owned(),
+        (Some(_), Some(_)) => "Connected, measuring latency...".to_owned(),
+        (Some(Matchmaking::Quickmatch), None) => "Looking for an opponent...".to_owned(),
+        (Some(Matchmaking::Private(code)), None) => format!("Waiting in room {code}, share the code with a friend"),
+    };
+    for mut text in &mut status_query {
+        text.sections[0].value.clone_from(&status);
+    }
+
+    let placeholder = "_".repeat(ROOM_CODE_LENGTH - typed_code.0.len());
+    for mut text in &mut code_query {
+        text.sections[0].value = format!("Code: {}{}", typed_code.0, placeholder);
+    }
+
+    let (choices, waiting) = match matchmaking {
+        None => (Display::Flex, Display::None),
+        Some(_) => (Display::None, Display::Flex),
+    };
+    for mut style in &mut choices_query {
+        style.display = choices;
+    }
+    for mut style in &mut waiting_query {
+        style.display = waiting;
+    }
+}
+
+fn update_mode_text(mode_choice: Res<ModeChoice>, mut query: Query<&mut Text, With<ModeText>>) {
+    for mut text in &mut query {
+        text.sections[0].value = format!("Mode: {}", mode_choice.mode.name());
+    }
+}
+
+/// Leaves the lobby as soon as the match starts.
+fn enter_game(session: Option<Res<Session<GgrsConfig>>>, mut state: ResMut<State<AppState>>) {
+    if session.is_some() {
+        state.set(AppState::InGame).ok();
+    }
+}
+
+/// Adds the lobby screen to the app, shown while in `AppState::Lobby`.
+pub fn build_lobby(app: &mut App) {
+    app.add_system_set(SystemSet::on_enter(AppState::Lobby).with_system(spawn_lobby))
+        .add_system_set(
+            SystemSet::on_update(AppState::Lobby)
+                .with_system(click_lobby_buttons)
+                .with_system(type_room_code)
+                .with_system(update_lobby)
+                .with_system(update_mode_text)
+                .with_system(enter_game),
+        )
+        .add_system_set(SystemSet::on_exit(AppState::Lobby).with_system(despawn_lobby));
+}
@@ -1,14 +1,261 @@
 use bevy::prelude::*;
-use game::*;
+use bifrost::ai::{Bot, Bots, Difficulty};
+use bifrost::game::*;
+use bifrost::latency::InputDelaySettings;
+use bifrost::netcode::{Matchmaking, ModeChoice, SessionType, SignalingServer};
+use bifrost::random::Seed;
+#[cfg(not(target_arch = "wasm32"))]
+use bifrost::savestate::SaveState;
+use bifrost::series::Series;
+use bifrost::simulation::{FrameRate, FRAME_RATE};
 
-mod components;
-mod game;
-mod input;
-mod netcode;
+
+#[cfg(not(target_arch = "wasm32"))]
+const DIRECT_USAGE: &str = "Usage: bifrost --udp=PORT --peer=HOST:PORT --player=1|2";
 
 
 fn main() {
+    let args = launch_args();
     let mut app = App::new();
-    build_app(&mut app);
+    let frame_rate = frame_rate(&args);
+    build_app(&mut app, session_type(&args), frame_rate.as_ref().copied().unwrap_or_default());
+    // Only logged once the app is built, nothing is logged before
+    if let Err(err) = frame_rate {
+        warn!("{err}, falling back to {FRAME_RATE}.");
+    }
+    app.insert_resource(bots(&args))
+        .insert_resource(input_delay_settings(&args))
+        .insert_resource(series(&args))
+        .insert_resource(seed(&args));
+    let mode_choice = mode_choice(&args);
+    app.insert_resource(mode_choice).insert_resource(mode_choice.mode);
+    if let Some(matchmaking) = matchmaking(&args) {
+        app.insert_resource(matchmaking);
+    }
+    if let Some(server) = signaling_server(&args) {
+        app.insert_resource(server);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(save_state) = save_state(&args) {
+        app.insert_resource(save_state);
+    }
     app.run();
-}
\ No newline at end of file
+}
+
+/// Launch options as `key` or `key=value` pairs.
+/// On the web they come from the page's query string, e.g. `http://127.0.0.1:1334/?local&ai=hard`.
+#[cfg(target_arch = "wasm32")]
+fn launch_args() -> Vec<String> {
+    let search = web_sys::window()
+        .and_then(|window| window.location().search().ok())
+        .unwrap_or_default();
+
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .filter(|arg| !arg.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Launch options as `key` or `key=value` pairs.
+/// Natively they come from the command line, e.g. `--local --ai=hard`.
+#[cfg(not(target_arch = "wasm32"))]
+fn launch_args() -> Vec<String> {
+    std::env::args()
+        .skip(1)
+        .map(|arg| arg.trim_start_matches("--").to_owned())
+        .collect()
+}
+
+/// Finds a launch option, returning its value or an empty string if it has none.
+fn launch_arg<'a>(args: &'a [String], key: &str) -> Option<&'a str> {
+    args.iter().find_map(|arg| match arg.split_once('=') {
+        Some((k, value)) if k == key => Some(value),
+        None if arg == key => Some(""),
+        _ => None,
+    })
+}
+
+/// What to play straight away, skipping the main menu. Playing against the AI happens locally too.
+fn session_type(args: &[String]) -> Option<SessionType> {
+    if launch_arg(args, "local").is_some() || launch_arg(args, "ai").is_some() {
+        return Some(SessionType::Local);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if launch_arg(args, "udp").is_some() {
+        match direct_session(args) {
+            Ok(session_type) => return Some(session_type),
+            Err(err) => {
+                eprintln!("{err}\n{DIRECT_USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // A bad room code opens the main menu instead
+    let online = ["online", "quickmatch", "bot"];
+    let online = online.iter().any(|key| launch_arg(args, key).is_some()) || room(args).is_some();
+    online.then_some(SessionType::Online)
+}
+
+/// Connecting straight to the other player, e.g. `--udp=7000 --peer=192.168.0.2:7000 --player=1`.
+/// There's nothing sensible to fall back on, so a bad option is reported with the usage and the game doesn't start.
+#[cfg(not(target_arch = "wasm32"))]
+fn direct_session(args: &[String]) -> Result<SessionType, String> {
+    let port = launch_arg(args, "udp").unwrap_or_default();
+    let port = port
+        .parse()
+        .map_err(|err| format!("Invalid --udp {port:?}: {err}, it needs a port to listen on."))?;
+
+    let peer = launch_arg(args, "peer").ok_or("--peer is missing, it needs the other player's address.")?;
+    let peer = std::net::ToSocketAddrs::to_socket_addrs(peer)
+        .map_err(|err| format!("Invalid --peer {peer:?}: {err}"))?
+        .next()
+        .ok_or_else(|| format!("Invalid --peer {peer:?}: it doesn't resolve to any address."))?;
+
+    let handle = match launch_arg(args, "player") {
+        Some("1") => 0,
+        Some("2") => 1,
+        Some(player) => return Err(format!("Invalid --player {player:?}: it needs to be 1 or 2.")),
+        None => return Err("--player is missing, it needs to be 1 or 2, and different from the other player's.".to_owned()),
+    };
+
+    Ok(SessionType::Direct { port, peer, handle })
+}
+
+/// `room=CODE` joins a private room straight away, which makes for shareable links. `quickmatch` skips the lobby too.
+fn matchmaking(args: &[String]) -> Option<Matchmaking> {
+    if let Some(code) = launch_arg(args, "room") {
+        match Matchmaking::join(code) {
+            Ok(room) => return Some(room),
+            Err(err) => warn!("{err}, not joining it."),
+        }
+    }
+    launch_arg(args, "quickmatch").map(|_| Matchmaking::Quickmatch)
+}
+
+/// The private room to join, if `room=CODE` names a valid one.
+fn room(args: &[String]) -> Option<Matchmaking> {
+    Matchmaking::join(launch_arg(args, "room")?).ok()
+}
+
+/// `server=HOST:PORT` points at the matchbox server. On the web it's otherwise expected on the host that served the page,
+/// which is where `bifrost-server` runs it.
+fn signaling_server(args: &[String]) -> Option<SignalingServer> {
+    if let Some(server) = launch_arg(args, "server").filter(|server| !server.is_empty()) {
+        return Some(SignalingServer(format!("ws://{server}")));
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let host = web_sys::window().and_then(|window| window.location().hostname().ok());
+        if let Some(host) = host.filter(|host| !host.is_empty()) {
+            return Some(SignalingServer::on_host(&host));
+        }
+    }
+
+    None
+}
+
+/// `ai` puts a bot in control of player 2, `bot` hands our own player to one, standing in for a human remote.
+fn bots(args: &[String]) -> Bots {
+    let mut bots = Bots::default();
+
+    let difficulty = |value: &str| {
+        value.parse::<Difficulty>().unwrap_or_else(|err| {
+            warn!("{err}, falling back to normal.");
+            Difficulty::Normal
+        })
+    };
+
+    if let Some(value) = launch_arg(args, "ai") {
+        bots.0.insert(1, Bot::new(difficulty(value)));
+    } else if let Some(value) = launch_arg(args, "bot") {
+        // Only local handles are ever asked for input, so this only takes over our own player
+        for handle in 0..2 {
+            bots.0.insert(handle, Bot::new(difficulty(value)));
+        }
+    }
+
+    bots
+}
+
+/// `min_delay=N` and `max_delay=N` bound the input delay picked from the latency, `renegotiate` picks it anew every round.
+fn input_delay_settings(args: &[String]) -> InputDelaySettings {
+    let mut settings = InputDelaySettings::default();
+
+    let frames = |key: &str, default: usize| {
+        launch_arg(args, key).map_or(default, |value| {
+            value.parse().unwrap_or_else(|err| {
+                warn!("Invalid {key} {value:?}: {err}, falling back to {default}.");
+                default
+            })
+        })
+    };
+
+    settings.min = frames("min_delay", settings.min);
+    settings.max = frames("max_delay", settings.max);
+    settings.renegotiate = launch_arg(args, "renegotiate").is_some();
+    settings
+}
+
+/// `mode=coop` plays co-op rather than versus. Joining a friend's room with `room=CODE` plays the mode they picked.
+fn mode_choice(args: &[String]) -> ModeChoice {
+    let mut choice = ModeChoice::default();
+    if let Some(value) = launch_arg(args, "mode") {
+        match value.parse() {
+            Ok(mode) => choice.mode = mode,
+            Err(err) => warn!("{err}, falling back to versus."),
+        }
+    }
+    choice.proposes = room(args).is_none();
+    choice
+}
+
+/// `best_of=N` plays series of N rounds, both players should pick the same.
+fn series(args: &[String]) -> Series {
+    let mut series = Series::default();
+    if let Some(value) = launch_arg(args, "best_of") {
+        match value.parse() {
+            Ok(best_of) => series.best_of = best_of,
+            Err(err) => warn!("Invalid best_of {value:?}: {err}, falling back to {}.", series.best_of),
+        }
+    }
+    series
+}
+
+/// `state=FILE` starts a local match from a saved state or bug report.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_state(args: &[String]) -> Option<SaveState> {
+    let path = launch_arg(args, "state").filter(|path| !path.is_empty())?;
+    match std::fs::read(path) {
+        Ok(bytes) => Some(SaveState::load(bytes)),
+        Err(err) => {
+            warn!("Failed to read {path}: {err}, starting a new match.");
+            None
+        }
+    }
+}
+
+/// `frame_rate=N` simulates N frames per second rather than 60. Online, both players have to pick the same,
+/// quickmatch and private rooms only pair players who did.
+fn frame_rate(args: &[String]) -> Result<FrameRate, String> {
+    let Some(value) = launch_arg(args, "frame_rate") else {
+        return Ok(FrameRate::default());
+    };
+    let frame_rate = value.parse().map_err(|err| format!("Invalid frame_rate {value:?}: {err}"))?;
+    FrameRate::new(frame_rate)
+}
+
+/// `seed=N` replays the same serves, walls and power-ups in local matches. Online, the players agree on a seed of their own.
+fn seed(args: &[String]) -> Seed {
+    if let Some(value) = launch_arg(args, "seed") {
+        match value.parse() {
+            Ok(seed) => return Seed(seed),
+            Err(err) => warn!("Invalid seed {value:?}: {err}, picking one at random."),
+        }
+    }
+    Seed(rand::random())
+}
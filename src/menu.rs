@@ -0,0 +1,241 @@
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use crate::ai::{Bot, Bots, Difficulty};
+use crate::channels::SharedSocket;
+use crate::game::AppState;
+use crate::latency::DelayNegotiation;
+use crate::netcode::*;
+use crate::random::Seed;
+use crate::reconnect::abandon_match;
+use crate::series::end_series;
+use crate::settings::spawn_settings;
+use crate::simulation::reset_simulation;
+use crate::ui::*;
+
+
+// Menu
+// The title screen picks what to play: online, locally or against the AI, and leads to the past matches. Escape opens a menu over the match,
+// with the settings and a way out. A rollback match can't wait for anyone, so it goes on behind the menu.
+
+const MATCH_MENU_KEY: KeyCode = KeyCode::Escape;
+const MATCH_MENU_BACKGROUND_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const MATCH_MENU_PADDING: Val = Val::Px(20.0);
+const NOTICE_FONT_SIZE: f32 = 20.0;
+const NOTICE_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+
+
+/// Everything on the title screen, despawned when leaving it.
+#[derive(Component)]
+struct MainMenuUi;
+
+/// What a title screen button does when clicked.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum MainMenuButton {
+    Online,
+    Local,
+    VersusAi,
+    Leaderboard,
+    History,
+    Settings,
+    #[cfg(not(target_arch = "wasm32"))]
+    Quit,
+}
+
+/// The menu over the match, while it's open.
+#[derive(Component)]
+struct MatchMenu;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum MatchMenuButton {
+    Resume,
+    Leave,
+}
+
+fn spawn_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = ui_font(&asset_server);
+    commands.spawn((screen(), MainMenuUi)).with_children(|parent| {
+        parent.spawn(ui_text("Bifrost", &font, TITLE_FONT_SIZE, UI_TEXT_COLOR));
+        spawn_button(parent, &font, "Play Online", BUTTON_SIZE, MainMenuButton::Online);
+        spawn_button(parent, &font, "Local", BUTTON_SIZE, MainMenuButton::Local);
+        spawn_button(parent, &font, "vs AI", BUTTON_SIZE, MainMenuButton::VersusAi);
+        spawn_button(parent, &font, "Leaderboard", BUTTON_SIZE, MainMenuButton::Leaderboard);
+        spawn_button(parent, &font, "History", BUTTON_SIZE, MainMenuButton::History);
+        spawn_button(parent, &font, "Settings", BUTTON_SIZE, MainMenuButton::Settings);
+        #[cfg(not(target_arch = "wasm32"))]
+        spawn_button(parent, &font, "Quit", BUTTON_SIZE, MainMenuButton::Quit);
+    });
+}
+
+fn despawn_main_menu(mut commands: Commands, query: Query<Entity, With<MainMenuUi>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Sets up the match picked on the title screen, online matches go through the lobby first.
+fn click_main_menu_buttons(
+    mut commands: Commands,
+    query: Query<(&Interaction, &MainMenuButton), Changed<Interaction>>,
+    mode_choice: Res<ModeChoice>,
+    mut state: ResMut<State<AppState>>,
+    #[cfg(not(target_arch = "wasm32"))] mut exit: EventWriter<bevy::app::AppExit>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match button {
+            MainMenuButton::Online => {
+                commands.insert_resource(SessionType::Online);
+                commands.insert_resource(Bots::default());
+                state.set(AppState::Lobby).ok();
+            }
+            MainMenuButton::Local => {
+                commands.insert_resource(SessionType::Local);
+                commands.insert_resource(mode_choice.mode);
+                commands.insert_resource(Bots::default());
+                state.set(AppState::InGame).ok();
+            }
+            MainMenuButton::VersusAi => {
+                let mut bots = Bots::default();
+                bots.0.insert(1, Bot::new(Difficulty::Normal));
+                commands.insert_resource(SessionType::Local);
+                commands.insert_resource(mode_choice.mode);
+                commands.insert_resource(bots);
+                state.set(AppState::InGame).ok();
+            }
+            MainMenuButton::Leaderboard => {
+                state.set(AppState::Leaderboard).ok();
+            }
+            MainMenuButton::History => {
+                state.set(AppState::History).ok();
+            }
+            MainMenuButton::Settings => {
+                state.set(AppState::Settings).ok();
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            MainMenuButton::Quit => exit.send(bevy::app::AppExit),
+        }
+    }
+}
+
+fn spawn_match_menu(commands: &mut Commands, font: Handle<Font>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            MatchMenu,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(MATCH_MENU_PADDING),
+                        ..default()
+                    },
+                    background_color: MATCH_MENU_BACKGROUND_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(ui_text("Menu", &font, TITLE_FONT_SIZE, UI_TEXT_COLOR));
+                    parent.spawn(ui_text("The match goes on meanwhile", &font, NOTICE_FONT_SIZE, NOTICE_COLOR));
+                    spawn_settings(parent, &font);
+                    parent.spawn(row()).with_children(|parent| {
+                        spawn_button(parent, &font, "Resume", BUTTON_SIZE, MatchMenuButton::Resume);
+                        spawn_button(parent, &font, "Leave Match", BUTTON_SIZE, MatchMenuButton::Leave);
+                    });
+                });
+        });
+}
+
+fn despawn_match_menu(mut commands: Commands, query: Query<Entity, With<MatchMenu>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Escape opens and closes the menu over the match.
+fn toggle_match_menu(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, With<MatchMenu>>,
+) {
+    if !keys.just_pressed(MATCH_MENU_KEY) {
+        return;
+    }
+
+    if query.is_empty() {
+        spawn_match_menu(&mut commands, ui_font(&asset_server));
+    }
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn click_match_menu_buttons(
+    mut commands: Commands,
+    query: Query<(&Interaction, &MatchMenuButton), Changed<Interaction>>,
+    menu_query: Query<Entity, With<MatchMenu>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match button {
+            MatchMenuButton::Resume => {
+                for entity in &menu_query {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            MatchMenuButton::Leave => {
+                commands.add(leave_match);
+                state.set(AppState::Menu).ok();
+            }
+        }
+    }
+}
+
+/// Ends the match along with the connection it was played over, and sets up a fresh world for the next.
+fn leave_match(world: &mut World) {
+    info!("Leaving the match");
+    world.remove_resource::<Session<GgrsConfig>>();
+    world.remove_resource::<Matchmaking>();
+    world.remove_resource::<P2PSession>();
+    world.remove_resource::<SharedSocket>();
+    world.remove_resource::<RemotePeer>();
+    world.remove_resource::<DelayNegotiation>();
+    world.remove_resource::<SessionPlayers>();
+    world.remove_resource::<LocalPlayerHandle>();
+    abandon_match(world);
+    end_series(world);
+
+    world.resource_mut::<Seed>().advance();
+    reset_simulation(world);
+}
+
+/// Adds the title screen to the app, shown while in `AppState::Menu`, and the menu over matches.
+pub fn build_menu(app: &mut App) {
+    app.add_system_set(SystemSet::on_enter(AppState::Menu).with_system(spawn_main_menu))
+        .add_system_set(SystemSet::on_update(AppState::Menu).with_system(click_main_menu_buttons))
+        .add_system_set(SystemSet::on_exit(AppState::Menu).with_system(despawn_main_menu))
+        .add_system_set(
+            SystemSet::on_update(AppState::InGame)
+                .with_system(toggle_match_menu)
+                .with_system(click_match_menu_buttons),
+        )
+        .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(despawn_match_menu));
+}
@@ -3,24 +3,160 @@ use bevy::{
     prelude::*,
     tasks::IoTaskPool
 };
-use bevy_ggrs::{*, ggrs::PlayerType};
+use bevy_ggrs::{*, ggrs::{Message, NonBlockingSocket, PlayerHandle, PlayerType}};
 use matchbox_socket::WebRtcSocket;
+use serde::{Deserialize, Serialize};
+use crate::channels::SharedSocket;
+use crate::latency::{DelayNegotiation, InputDelaySettings};
+use crate::reconnect::Greeting;
+use crate::simulation::{FrameRate, GameMode};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
 
-const INPUT_DELAY: usize = 2;
 const MAX_PLAYER_CONNECTIONS: usize = 2;
-const HOST: &str = "ws://127.0.0.1";
-const PORT: &str = "3536";
+const HOST: &str = "127.0.0.1";
+const PORT: u16 = 3536;
 const ROOM: &str = "bifrost";
+const CO_OP_ROOM: &str = "bifrost-coop";
+
+/// Characters room codes are made of, leaving out those easily mistaken for one another like O and 0.
+pub const ROOM_CODE_CHARACTERS: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+pub const ROOM_CODE_LENGTH: usize = 4;
+
+/// How the players of a match are connected.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SessionType {
+    /// Each player plays in their own browser, connected through the matchbox server.
+    #[default]
+    Online,
+    /// Both players share one machine and keyboard, no networking involved.
+    Local,
+    /// Natively, players can skip signaling and connect straight to each other over UDP.
+    #[cfg(not(target_arch = "wasm32"))]
+    Direct {
+        /// Local port to listen on.
+        port: u16,
+        /// Address of the other player.
+        peer: SocketAddr,
+        /// Which of the two players we are, both ends have to agree on this.
+        handle: PlayerHandle,
+    },
+}
+
+/// The address of the matchbox server introducing players to each other, e.g. `ws://127.0.0.1:3536`.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct SignalingServer(pub String);
+
+impl Default for SignalingServer {
+    fn default() -> Self {
+        SignalingServer::on_host(HOST)
+    }
+}
+
+impl SignalingServer {
+    /// The server on the given host, at the default port.
+    pub fn on_host(host: &str) -> SignalingServer {
+        SignalingServer(format!("ws://{}:{}", host, PORT))
+    }
+}
+
+/// Which room of the matchbox server to meet the other player in.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub enum Matchmaking {
+    /// The shared room, paired with whoever comes next.
+    Quickmatch,
+    /// A private room that only those who know its code can join.
+    Private(String),
+}
+
+/// The mode we'd like to play in. Online, both players propose one when they meet, unless one of them joined
+/// the other's room, taking the mode it was made for.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModeChoice {
+    pub mode: GameMode,
+    pub proposes: bool,
+}
+
+impl Default for ModeChoice {
+    fn default() -> Self {
+        ModeChoice {
+            mode: GameMode::default(),
+            proposes: true,
+        }
+    }
+}
+
+impl Matchmaking {
+    /// A private room with a fresh random code.
+    pub fn new_private() -> Matchmaking {
+        let characters = ROOM_CODE_CHARACTERS.as_bytes();
+        let code = (0..ROOM_CODE_LENGTH)
+            .map(|_| characters[rand::random::<usize>() % characters.len()] as char)
+            .collect();
+        Matchmaking::Private(code)
+    }
+
+    /// The private room with the given code, whichever case it's typed or shared in.
+    /// Codes go into the signaling server's URL, so anything but a well-formed one is refused.
+    pub fn join(code: &str) -> Result<Matchmaking, String> {
+        let code = code.to_uppercase();
+        if code.chars().count() != ROOM_CODE_LENGTH || !code.chars().all(|c| ROOM_CODE_CHARACTERS.contains(c)) {
+            return Err(format!(
+                "Invalid room code: {code:?}, codes are {ROOM_CODE_LENGTH} of {ROOM_CODE_CHARACTERS}"
+            ));
+        }
+        Ok(Matchmaking::Private(code))
+    }
+
+    /// Quickmatch pairs players wanting the same mode, in a room for each.
+    /// Players simulating at another frame rate than the default meet in rooms of their own, they can't play together.
+    fn room(&self, mode: GameMode, frame_rate: FrameRate) -> String {
+        let room = match (self, mode) {
+            (Matchmaking::Quickmatch, GameMode::Versus) => ROOM.to_owned(),
+            (Matchmaking::Quickmatch, GameMode::CoOp) => CO_OP_ROOM.to_owned(),
+            (Matchmaking::Private(code), _) => format!("{}-{}", ROOM, code),
+        };
+        if frame_rate == FrameRate::default() {
+            room
+        } else {
+            format!("{}-{}fps", room, frame_rate.0)
+        }
+    }
+}
 
 #[derive(Resource)]
 pub struct P2PSession {
     socket: Option<WebRtcSocket>,
 }
 
+/// The players of the connection, ordered by handle.
+/// A session with them starts once they've agreed on an input delay, and again every round.
 #[derive(Resource)]
-struct LocalPlayerHandle(usize);
+pub struct SessionPlayers {
+    players: Vec<PlayerType<String>>,
+}
+
+impl SessionPlayers {
+    pub fn new(players: Vec<PlayerType<String>>) -> SessionPlayers {
+        SessionPlayers { players }
+    }
+
+    /// The players of a match against `peer`, where we are the player with `handle`.
+    pub fn with_local_handle(handle: PlayerHandle, peer: String) -> SessionPlayers {
+        let mut players = vec![PlayerType::Local; MAX_PLAYER_CONNECTIONS];
+        players[1 - handle] = PlayerType::Remote(peer);
+        SessionPlayers { players }
+    }
+}
 
-#[derive(Resource, Default, Reflect, Hash)]
+/// The address of the other player, once connected.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct RemotePeer(pub String);
+
+#[derive(Resource)]
+pub struct LocalPlayerHandle(pub usize);
+
+#[derive(Resource, Default, Reflect, FromReflect, Hash, Serialize, Deserialize)]
 #[reflect(Hash)]
 pub struct FrameCount {
     pub frame: u32,
@@ -37,15 +173,38 @@ impl ggrs::Config for GgrsConfig {
 }
 
 
+/// Stands in for a real socket in sessions where every player is local, there's nobody to talk to.
+struct LocalSocket;
+
+impl NonBlockingSocket<String> for LocalSocket {
+    fn send_to(&mut self, _msg: &Message, _addr: &String) {}
+
+    fn receive_all_messages(&mut self) -> Vec<(String, Message)> {
+        Vec::new()
+    }
+}
+
+
 /// Increases the frame count by 1 every update step. If loading and saving resources works correctly,
 /// you should see this resource rolling back, counting back up and finally increasing by 1 every update step.
 pub fn increase_frame_system(mut frame_count: ResMut<FrameCount>) {
     frame_count.frame += 1;
 }
 
-/// Opens a WebRTC socket where players can connect to.
-pub fn start_matchbox_socket(mut commands: Commands) {
-    let room_url = format!("{}:{}/{}?next={}", HOST, PORT, ROOM, MAX_PLAYER_CONNECTIONS);
+/// Opens a WebRTC socket where players can connect to, once it's decided which room to join.
+pub fn start_matchbox_socket(
+    mut commands: Commands,
+    matchmaking: Option<Res<Matchmaking>>,
+    mode_choice: Res<ModeChoice>,
+    frame_rate: Res<FrameRate>,
+    server: Res<SignalingServer>,
+) {
+    let Some(matchmaking) = matchmaking.filter(|matchmaking| matchmaking.is_added()) else {
+        return;
+    };
+
+    let room = matchmaking.room(mode_choice.mode, *frame_rate);
+    let room_url = format!("{}/{}?next={}", server.0, room, MAX_PLAYER_CONNECTIONS);
     info!("Connecting to matchbox server: {:?}", room_url);
     let (socket, message_loop) = WebRtcSocket::new(room_url);
 
@@ -58,10 +217,17 @@ pub fn start_matchbox_socket(mut commands: Commands) {
     });
 }
 
-/// Creates a P2P Session between players.
-pub fn wait_for_players(mut commands: Commands, mut session: ResMut<P2PSession>) {
+/// Waits for the other player to connect, then has both greet each other and measure their latency before starting a session.
+pub fn wait_for_players(
+    mut commands: Commands,
+    session: Option<ResMut<P2PSession>>,
+    matchmaking: Option<Res<Matchmaking>>,
+) {
+    let Some(mut session) = session else {
+        return; // Not looking for a match
+    };
     let Some(socket) = &mut session.socket else {
-        // If there is no socket we've already started the game
+        // If there is no socket we've already moved on
         return;
     };
 
@@ -73,29 +239,124 @@ pub fn wait_for_players(mut commands: Commands, mut session: ResMut<P2PSession>)
     }
 
     info!("All players have connected!");
-    
+
+    let peer = players
+        .iter()
+        .find_map(|player| match player {
+            PlayerType::Remote(peer) => Some(peer.clone()),
+            _ => None,
+        })
+        .expect("The other player should be remote.");
+
+    // Move the socket out of the resource, from now on it's shared between GGRS and everything else we send
+    let socket = session.socket.take().unwrap();
+    commands.insert_resource(SharedSocket::new(socket));
+    commands.insert_resource(RemotePeer(peer.clone()));
+    commands.insert_resource(DelayNegotiation::new(peer.clone()));
+    match matchmaking.as_deref() {
+        Some(Matchmaking::Private(_)) => commands.insert_resource(Greeting::private(peer, players)),
+        _ => commands.insert_resource(Greeting::new(peer, players)),
+    }
+}
+
+/// Creates a P2P Session between players, as soon as they agree on an input delay and no session is running.
+pub fn start_p2p_session(
+    mut commands: Commands,
+    session: Option<Res<Session<GgrsConfig>>>,
+    players: Option<Res<SessionPlayers>>,
+    negotiation: Option<Res<DelayNegotiation>>,
+    settings: Res<InputDelaySettings>,
+    frame_rate: Res<FrameRate>,
+    socket: Option<Res<SharedSocket>>,
+) {
+    if session.is_some() {
+        return;
+    }
+    let (Some(players), Some(negotiation), Some(socket)) = (players, negotiation, socket) else {
+        return;
+    };
+    let Some(input_delay) = negotiation.input_delay(&settings, *frame_rate) else {
+        return; // Still measuring
+    };
+
+    info!("Starting the session with an input delay of {} frames", input_delay);
+
     // Create a GGRS P2P session
     let mut session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
         .with_num_players(MAX_PLAYER_CONNECTIONS)
-        .with_input_delay(INPUT_DELAY);
+        .with_fps(frame_rate.0)
+        .expect("The frame rate is above zero.")
+        .with_input_delay(input_delay);
 
-    for (i, player) in players.into_iter().enumerate() {
-        if player == PlayerType::Local {
+    for (i, player) in players.players.iter().enumerate() {
+        if *player == PlayerType::Local {
             commands.insert_resource(LocalPlayerHandle(i));
         }
 
         session_builder = session_builder
-            .add_player(player, i)
+            .add_player(player.clone(), i)
             .expect("Player failed to join.");
     }
 
-    // Move the socket out of the resource (required because GGRS takes ownership of it)
-    let socket = session.socket.take().unwrap();
-
     // Start session
     let ggrs_session = session_builder
-        .start_p2p_session(socket)
+        .start_p2p_session(socket.ggrs_socket())
         .expect("Session failed to start.");
 
-    commands.insert_resource(bevy_ggrs::Session::P2PSession(ggrs_session));
-}
\ No newline at end of file
+    commands.insert_resource(Session::P2PSession(ggrs_session));
+}
+
+/// Creates a session where both players are local, running through the same GGRS schedule as online play.
+/// Like online sessions, it starts again whenever the last one ends.
+pub fn start_local_session(
+    mut commands: Commands,
+    session: Option<Res<Session<GgrsConfig>>>,
+    frame_rate: Res<FrameRate>,
+) {
+    if session.is_some() {
+        return;
+    }
+
+    let mut session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(MAX_PLAYER_CONNECTIONS)
+        .with_fps(frame_rate.0)
+        .expect("The frame rate is above zero.");
+
+    for i in 0..MAX_PLAYER_CONNECTIONS {
+        session_builder = session_builder
+            .add_player(PlayerType::Local, i)
+            .expect("Player failed to join.");
+    }
+
+    // Without any remotes there's nothing to synchronize, so the session runs right away
+    let ggrs_session = session_builder
+        .start_p2p_session(LocalSocket)
+        .expect("Session failed to start.");
+
+    commands.insert_resource(Session::P2PSession(ggrs_session));
+}
+
+/// Connects straight to the peer's UDP socket, without any signaling server.
+/// Should the connection be dropped, it connects again.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn start_udp_session(mut commands: Commands, session_type: Res<SessionType>, socket: Option<Res<SharedSocket>>) {
+    let SessionType::Direct { port, peer, handle } = *session_type else {
+        return;
+    };
+    if socket.is_some() {
+        return;
+    }
+
+    info!("Connecting to {} on port {} as player {}", peer, port, handle + 1);
+    let socket = std::net::UdpSocket::bind(("0.0.0.0", port)).expect("Failed to bind UDP socket.");
+    socket.set_nonblocking(true).expect("Failed to make UDP socket non-blocking.");
+
+    // Packets are checked against this address, so it has to be in the same form the socket reports them
+    let peer = peer.to_string();
+    let players = SessionPlayers::with_local_handle(handle, peer.clone()).players;
+
+    commands.insert_resource(SharedSocket::new(socket));
+    commands.insert_resource(RemotePeer(peer.clone()));
+    commands.insert_resource(DelayNegotiation::new(peer.clone()));
+    commands.insert_resource(Greeting::private(peer, players));
+}
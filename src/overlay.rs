@@ -0,0 +1,164 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ggrs::Session;
+use std::fmt::Write;
+use crate::latency::{DelayNegotiation, InputDelaySettings};
+use crate::netcode::GgrsConfig;
+use crate::rollback::{RollbackStats, TimeSyncStats};
+use crate::simulation::FrameRate;
+
+
+// Network Overlay
+// Debug readout of how the session is doing, toggled with F3.
+// Rollback quality is the point of this project, so it should be visible while playing.
+
+const OVERLAY_TOGGLE_KEY: KeyCode = KeyCode::F3;
+const OVERLAY_FONT_SIZE: f32 = 18.0;
+const OVERLAY_TEXT_PADDING: Val = Val::Px(5.0);
+const OVERLAY_COLOR: Color = Color::rgb(0.9, 0.9, 0.5);
+
+// How often the readout refreshes, slower than every frame so the numbers can be read
+const OVERLAY_REFRESH_SECONDS: f32 = 0.5;
+
+
+#[derive(Component)]
+struct NetworkOverlay;
+
+/// Rollback and time sync counts as of the last refresh, to turn the running totals into rates.
+#[derive(Resource)]
+struct OverlaySampler {
+    timer: Timer,
+    last_stats: RollbackStats,
+    last_time_sync: TimeSyncStats,
+}
+
+impl Default for OverlaySampler {
+    fn default() -> Self {
+        OverlaySampler {
+            timer: Timer::from_seconds(OVERLAY_REFRESH_SECONDS, TimerMode::Repeating),
+            last_stats: RollbackStats::default(),
+            last_time_sync: TimeSyncStats::default(),
+        }
+    }
+}
+
+/// Everything the overlay reads out.
+#[derive(SystemParam)]
+struct Readings<'w, 's> {
+    stats: Res<'w, RollbackStats>,
+    time_sync: Res<'w, TimeSyncStats>,
+    session: Option<Res<'w, Session<GgrsConfig>>>,
+    negotiation: Option<Res<'w, DelayNegotiation>>,
+    delay_settings: Res<'w, InputDelaySettings>,
+    frame_rate: Res<'w, FrameRate>,
+    #[system_param(ignore)]
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+fn spawn_overlay(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("../assets/fonts/FiraMono-Medium.ttf"),
+                font_size: OVERLAY_FONT_SIZE,
+                color: OVERLAY_COLOR,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: OVERLAY_TEXT_PADDING,
+                right: OVERLAY_TEXT_PADDING,
+                ..default()
+            },
+            ..default()
+        }),
+        NetworkOverlay,
+    ))
+    .insert(Visibility { is_visible: false });
+}
+
+fn toggle_overlay(keys: Res<Input<KeyCode>>, mut query: Query<&mut Visibility, With<NetworkOverlay>>) {
+    if keys.just_pressed(OVERLAY_TOGGLE_KEY) {
+        for mut visibility in &mut query {
+            visibility.is_visible = !visibility.is_visible;
+        }
+    }
+}
+
+fn update_overlay(
+    time: Res<Time>,
+    mut sampler: ResMut<OverlaySampler>,
+    readings: Readings,
+    mut query: Query<(&mut Text, &Visibility), With<NetworkOverlay>>,
+) {
+    if !sampler.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Readings { stats, time_sync, session, negotiation, delay_settings, frame_rate, .. } = readings;
+    let elapsed = sampler.timer.duration().as_secs_f32();
+    let rollbacks_per_second = (stats.rollbacks - sampler.last_stats.rollbacks) as f32 / elapsed;
+    let resimulated_per_second = (stats.resimulated_frames - sampler.last_stats.resimulated_frames) as f32 / elapsed;
+    let slowed_per_second = (time_sync.slowed_frames - sampler.last_time_sync.slowed_frames) as f32 / elapsed;
+    sampler.last_stats = *stats;
+    sampler.last_time_sync = *time_sync;
+
+    let Ok((mut text, visibility)) = query.get_single_mut() else {
+        return;
+    };
+    if !visibility.is_visible {
+        return;
+    }
+
+    let mut readout = String::new();
+    match session.as_deref() {
+        None => readout.push_str("no session\n"),
+        Some(Session::SyncTestSession(_)) => readout.push_str("synctest session\n"),
+        Some(Session::SpectatorSession(_)) => readout.push_str("spectator session\n"),
+        Some(Session::P2PSession(session)) => {
+            let predicted_frames = session.current_frame() - session.confirmed_frame().max(0);
+            writeln!(readout, "frame {} ({predicted_frames} predicted)", session.current_frame()).ok();
+            if let Some(negotiation) = negotiation.as_deref() {
+                let input_delay = negotiation.input_delay(&delay_settings, *frame_rate);
+                if let (Some(rtt_ms), Some(input_delay)) = (negotiation.rtt_ms(), input_delay) {
+                    writeln!(readout, "input delay {input_delay} frames (rtt {rtt_ms} ms)").ok();
+                }
+            }
+            writeln!(readout, "ahead {} frames, slowed {slowed_per_second:.1} frames/s", time_sync.frames_ahead).ok();
+            writeln!(
+                readout,
+                "skipped {} frames on {} waits",
+                time_sync.skipped_frames, time_sync.wait_recommendations
+            )
+            .ok();
+
+            for handle in session.remote_player_handles() {
+                match session.network_stats(handle) {
+                    Ok(network_stats) => {
+                        writeln!(readout, "player {handle}").ok();
+                        writeln!(readout, "  ping {} ms", network_stats.ping).ok();
+                        writeln!(readout, "  send queue {}", network_stats.send_queue_len).ok();
+                        writeln!(readout, "  sent {} kbps", network_stats.kbps_sent).ok();
+                        writeln!(readout, "  local behind {} frames", network_stats.local_frames_behind).ok();
+                        writeln!(readout, "  remote behind {} frames", network_stats.remote_frames_behind).ok();
+                    }
+                    Err(_) => {
+                        writeln!(readout, "player {handle}: synchronizing").ok();
+                    }
+                }
+            }
+        }
+    }
+    writeln!(readout, "rollbacks {rollbacks_per_second:.1}/s").ok();
+    write!(readout, "resimulated {resimulated_per_second:.1} frames/s").ok();
+
+    text.sections[0].value = readout;
+}
+
+/// Adds the network overlay to the app, hidden until toggled.
+pub fn build_overlay(app: &mut App) {
+    app.init_resource::<OverlaySampler>()
+        .add_startup_system(spawn_overlay)
+        .add_system(toggle_overlay)
+        .add_system(update_overlay);
+}
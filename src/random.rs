@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+
+// Random
+// Everything left to chance in the simulation draws from one generator that's rolled back along with the world,
+// so peers draw the same numbers on every frame, resimulated or not. Only integer arithmetic and exactly rounded
+// float operations go into a draw, which keeps them the same on every platform too.
+// Draws have to come in an order that doesn't depend on how entities are stored, rollbacks shuffle those.
+
+/// What the randomness of the next round grows from. Peers agree on it when they meet, local matches pick their own.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Seed(pub u64);
+
+impl Seed {
+    /// A seed both peers come up with from the nonces they greeted each other with, whichever way around.
+    pub fn agreed(ours: u64, theirs: u64) -> Seed {
+        Seed(ours ^ theirs)
+    }
+
+    /// Moves on to the seed of the next round, so rounds don't repeat each other.
+    pub fn advance(&mut self) {
+        self.0 = RollbackRng::new(*self).next_u64();
+    }
+}
+
+/// A SplitMix64 generator, its whole state is one number that's cheap to save on every frame.
+#[derive(Resource, Default, Reflect, FromReflect, Hash, Serialize, Deserialize)]
+#[reflect(Hash)]
+pub struct RollbackRng {
+    state: u64,
+}
+
+impl RollbackRng {
+    pub fn new(seed: Seed) -> RollbackRng {
+        RollbackRng { state: seed.0 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: u32) -> u32 {
+        (((self.next_u64() >> 32) * n as u64) >> 32) as u32
+    }
+
+    /// True once in `n` draws, on average.
+    pub fn one_in(&mut self, n: u32) -> bool {
+        self.below(n) == 0
+    }
+
+    /// A number in `min..max`, from 24 random bits, which any f32 holds exactly.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_u64() >> 40) as f32 / (1u32 << 24) as f32;
+        min + (max - min) * unit
+    }
+}
@@ -0,0 +1,516 @@
+use bevy::{
+    prelude::*,
+    utils::{Duration, Instant},
+};
+use bevy_ggrs::{
+    ggrs::{GGRSEvent, PlayerHandle, PlayerType},
+    Session,
+};
+use serde::{Deserialize, Serialize};
+use crate::channels::{ControlEvent, ControlMessage, SharedSocket};
+use crate::latency::DelayNegotiation;
+use crate::netcode::*;
+use crate::random::Seed;
+use crate::rollback::{ConfirmedWorld, RollbackRegistry, SessionEvent};
+use crate::series::Series;
+use crate::simulation::{fit_arena, reset_simulation, GameMode};
+use crate::snapshot::{SnapshotError, WorldSnapshot};
+
+
+// Reconnect
+// A player who drops out of a match can come back to it. The player who stayed waits in the same room, and once the
+// other is back, sends them the rollback world as of the last frame both players' inputs were in for, going back to it
+// as well. Both then start a fresh GGRS session from it.
+// Matches are told apart by an id both players agree on, so only the player who was in a match can resume it.
+// A player who lost track of the match, say by restarting the game, only gets it back in a private room or over
+// a direct connection, where nobody else could be the one arriving. In the shared room that's a stranger,
+// who starts a new match with the player who waited instead.
+
+// Resume packets carry this many bytes of the match at most, staying well clear of the largest packet that gets through
+const CHUNK_SIZE: usize = 1024;
+const RESEND_INTERVAL: Duration = Duration::from_millis(250);
+
+const RECONNECT_FONT_SIZE: f32 = 30.0;
+const RECONNECT_TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const RECONNECT_BACKGROUND_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const RECONNECT_TEXT_PADDING: Val = Val::Px(20.0);
+
+
+/// Tells matches apart, so an interrupted match is only resumed with the player who was in it.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchId(pub u64);
+
+impl MatchId {
+    /// An id both peers come up with from the nonces they greeted each other with, whichever way around.
+    pub fn agreed(ours: u64, theirs: u64) -> MatchId {
+        MatchId(ours.wrapping_add(theirs))
+    }
+}
+
+/// The frame and id of an interrupted match, as offered to the peer.
+type ResumeOffer = (u32, MatchId);
+
+/// A match whose other player dropped out, kept by whoever stayed until they're back.
+#[derive(Resource)]
+pub struct Interrupted {
+    /// Our player in the match
+    handle: PlayerHandle,
+    id: MatchId,
+}
+
+/// What the peers tell each other when they connect, to decide whether they start a match or resume one.
+#[derive(Resource)]
+pub struct Greeting {
+    peer: String,
+    /// The players as the socket ordered them, for a match that starts from scratch
+    players: Vec<PlayerType<String>>,
+    nonce: u64,
+    /// The frame and id of the match we'd resume, settled when we first greet
+    resume: Option<ResumeOffer>,
+    /// The mode we propose for a new match, settled when we first greet
+    mode: Option<GameMode>,
+    /// The peer's `resume`, proposed mode and nonce, once we've heard from them
+    heard: Option<(Option<ResumeOffer>, Option<GameMode>, u64)>,
+    /// Whether nobody but the other player of an interrupted match could be the peer
+    private: bool,
+    last_sent: Option<Instant>,
+}
+
+impl Greeting {
+    /// Greets a peer met in the shared room, where anyone may turn up.
+    pub fn new(peer: String, players: Vec<PlayerType<String>>) -> Greeting {
+        Greeting {
+            peer,
+            players,
+            nonce: rand::random(),
+            resume: None,
+            mode: None,
+            heard: None,
+            private: false,
+            last_sent: None,
+        }
+    }
+
+    /// Greets a peer met in a private room or connected to directly, who knew where to find us.
+    pub fn private(peer: String, players: Vec<PlayerType<String>>) -> Greeting {
+        Greeting {
+            private: true,
+            ..Greeting::new(peer, players)
+        }
+    }
+
+    fn hello(&self) -> ControlMessage {
+        ControlMessage::Hello {
+            resume: self.resume.map(|(frame, id)| (frame, id.0)),
+            mode: self.mode,
+            nonce: self.nonce,
+            heard: self.heard.is_some(),
+        }
+    }
+}
+
+/// Everything the returning player needs to pick the match up where it was.
+#[derive(Serialize, Deserialize)]
+struct ResumeState {
+    /// The sender's player, the returning player takes the other
+    handle: PlayerHandle,
+    id: MatchId,
+    series: Series,
+    /// For the rounds still to come
+    seed: Seed,
+    snapshot: Vec<u8>,
+}
+
+/// The match on its way to the returning player, in chunks.
+#[derive(Resource)]
+struct OutgoingResume {
+    handle: PlayerHandle,
+    chunks: Vec<Vec<u8>>,
+    acknowledged: Vec<bool>,
+    last_sent: Option<Instant>,
+}
+
+/// The chunks of the match received so far, while returning to it.
+#[derive(Resource, Default)]
+struct IncomingResume {
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+#[derive(Component)]
+struct ReconnectBox;
+
+#[derive(Component)]
+struct ReconnectText;
+
+fn spawn_reconnect_box(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    display: Display::None,
+                    ..default()
+                },
+                ..default()
+            },
+            ReconnectBox,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        padding: UiRect::all(RECONNECT_TEXT_PADDING),
+                        ..default()
+                    },
+                    background_color: RECONNECT_BACKGROUND_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font: asset_server.load("../assets/fonts/FiraSans-Bold.ttf"),
+                                font_size: RECONNECT_FONT_SIZE,
+                                color: RECONNECT_TEXT_COLOR,
+                            },
+                        ),
+                        ReconnectText,
+                    ));
+                });
+        });
+}
+
+/// Holds on to the match when the other player drops out, and goes back to waiting for them where we met.
+fn interrupt_match(
+    mut commands: Commands,
+    mut events: EventReader<SessionEvent>,
+    peer: Option<Res<RemotePeer>>,
+    local_handle: Option<Res<LocalPlayerHandle>>,
+    id: Option<Res<MatchId>>,
+    matchmaking: Option<Res<Matchmaking>>,
+) {
+    let (Some(peer), Some(local_handle), Some(id)) = (peer, local_handle, id) else {
+        events.clear();
+        return;
+    };
+    let dropped = events
+        .iter()
+        .any(|event| matches!(&event.0, GGRSEvent::Disconnected { addr } if *addr == peer.0));
+    if !dropped {
+        return;
+    }
+
+    info!("The other player dropped out, waiting for them to come back");
+    commands.insert_resource(Interrupted { handle: local_handle.0, id: *id });
+
+    // The frames since the last confirmed one went by our guess of the other player's inputs, they're taken back
+    commands.add(|world: &mut World| {
+        if let Some(ConfirmedWorld(snapshot)) = world.remove_resource::<ConfirmedWorld>() {
+            let registry = world.resource::<RollbackRegistry>().0.clone();
+            snapshot.write_to_world(world, &registry);
+        }
+    });
+
+    // Without a session the world stands still, just as the other player left it
+    commands.remove_resource::<Session<GgrsConfig>>();
+    commands.remove_resource::<P2PSession>();
+    commands.remove_resource::<SharedSocket>();
+    commands.remove_resource::<RemotePeer>();
+    commands.remove_resource::<DelayNegotiation>();
+    commands.remove_resource::<SessionPlayers>();
+    commands.remove_resource::<Greeting>();
+
+    // Joining the room anew opens a new socket there, direct connections open theirs once the old one is gone
+    if let Some(matchmaking) = matchmaking {
+        commands.insert_resource(matchmaking.clone());
+    }
+}
+
+/// The mode of a new match: the one proposed, or the one proposed with the higher nonce should both have proposed one.
+fn agreed_mode(ours: (Option<GameMode>, u64), theirs: (Option<GameMode>, u64)) -> GameMode {
+    match (ours, theirs) {
+        ((Some(ours), our_nonce), (Some(theirs), their_nonce)) => {
+            if our_nonce > their_nonce { ours } else { theirs }
+        }
+        ((Some(mode), _), (None, _)) | ((None, _), (Some(mode), _)) => mode,
+        ((None, _), (None, _)) => GameMode::default(),
+    }
+}
+
+/// Whether we resume the match, the peer does, or neither of us and a new match starts.
+/// Given the same greetings both peers come to the same answer, each from their side.
+fn resumed_by_us(ours: (Option<ResumeOffer>, u64), theirs: (Option<ResumeOffer>, u64), private: bool) -> Option<bool> {
+    match (ours, theirs) {
+        // Whoever has the later frame resumes should both have the match
+        ((Some((our_frame, our_id)), our_nonce), (Some((their_frame, their_id)), their_nonce)) if our_id == their_id => {
+            Some((our_frame, our_nonce) > (their_frame, their_nonce))
+        }
+        ((Some(_), _), (None, _)) if private => Some(true),
+        ((None, _), (Some(_), _)) if private => Some(false),
+        _ => None,
+    }
+}
+
+/// Greets the peer until we've heard from them, then decides how the match starts: from scratch, resumed by us,
+/// or resumed by them.
+/// A match from scratch is seeded with both nonces, which makes for a seed that both peers agree on without another word.
+fn greet(
+    mut commands: Commands,
+    socket: Option<Res<SharedSocket>>,
+    greeting: Option<ResMut<Greeting>>,
+    interrupted: Option<Res<Interrupted>>,
+    frame_count: Res<FrameCount>,
+    mode_choice: Res<ModeChoice>,
+    mut events: EventReader<ControlEvent>,
+) {
+    let (Some(socket), Some(mut greeting)) = (socket, greeting) else {
+        events.clear();
+        return;
+    };
+    if greeting.is_added() {
+        greeting.resume = interrupted.as_ref().map(|interrupted| (frame_count.frame, interrupted.id));
+        greeting.mode = mode_choice.proposes.then_some(mode_choice.mode);
+    }
+
+    let already_heard = greeting.heard.is_some();
+    for event in events.iter() {
+        let ControlMessage::Hello { resume, mode, nonce, heard } = event.message else {
+            continue;
+        };
+        if event.peer != greeting.peer {
+            continue;
+        }
+
+        greeting.heard = Some((resume.map(|(frame, id)| (frame, MatchId(id))), mode, nonce));
+        if !heard {
+            socket.send_control(&greeting.hello(), &greeting.peer);
+        }
+    }
+
+    let Some((peer_resume, peer_mode, peer_nonce)) = greeting.heard else {
+        let due = greeting
+            .last_sent
+            .map_or(true, |last_sent| last_sent.elapsed() >= RESEND_INTERVAL);
+        if due {
+            greeting.last_sent = Some(Instant::now());
+            socket.send_control(&greeting.hello(), &greeting.peer);
+        }
+        return;
+    };
+    if already_heard {
+        return;
+    }
+
+    let resumed = resumed_by_us((greeting.resume, greeting.nonce), (peer_resume, peer_nonce), greeting.private);
+    if resumed == Some(true) {
+        let interrupted = interrupted.expect("Only an interrupted match is resumed");
+        let (handle, id) = (interrupted.handle, interrupted.id);
+        info!("Resuming the match for the returning player");
+        commands.add(move |world: &mut World| prepare_resume(world, handle, id));
+    } else if resumed == Some(false) {
+        info!("Returning to the match in progress");
+        commands.remove_resource::<Interrupted>();
+        commands.insert_resource(IncomingResume::default());
+    } else {
+        if interrupted.is_some() {
+            info!("Someone other than the player who dropped out joined, giving up on the interrupted match");
+            commands.remove_resource::<Interrupted>();
+            commands.add(|world: &mut World| {
+                let best_of = world.resource::<Series>().best_of;
+                world.insert_resource(Series::new(best_of));
+            });
+        }
+
+        // A new match, its randomness grown from both our nonces
+        let mode = agreed_mode((greeting.mode, greeting.nonce), (peer_mode, peer_nonce));
+        info!("Starting a new match in {:?}", mode);
+        commands.insert_resource(mode);
+        commands.insert_resource(MatchId::agreed(greeting.nonce, peer_nonce));
+        commands.insert_resource(Seed::agreed(greeting.nonce, peer_nonce));
+        commands.add(reset_simulation);
+        commands.insert_resource(SessionPlayers::new(greeting.players.clone()));
+    }
+}
+
+/// Packs up the match for the returning player.
+fn prepare_resume(world: &mut World, handle: PlayerHandle, id: MatchId) {
+    let registry = world.resource::<RollbackRegistry>().0.clone();
+    let snapshot = WorldSnapshot::from_world(world, &registry)
+        .to_bytes(&registry)
+        .expect("Every rollback type should be serializable");
+    let mut series = world.resource::<Series>().clone();
+    series.forget_peer_readiness();
+
+    let state = ResumeState {
+        handle,
+        id,
+        series,
+        seed: *world.resource::<Seed>(),
+        snapshot,
+    };
+    let bytes = bincode::serialize(&state).expect("Resume states always serialize");
+    let chunks: Vec<Vec<u8>> = bytes.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect();
+
+    world.resource_mut::<Series>().forget_peer_readiness();
+    world.insert_resource(OutgoingResume {
+        handle,
+        acknowledged: vec![false; chunks.len()],
+        chunks,
+        last_sent: None,
+    });
+}
+
+/// Sends the match until the returning player has every piece of it, then starts a session with them.
+fn send_resume(
+    mut commands: Commands,
+    socket: Option<Res<SharedSocket>>,
+    peer: Option<Res<RemotePeer>>,
+    outgoing: Option<ResMut<OutgoingResume>>,
+    mut events: EventReader<ControlEvent>,
+) {
+    let (Some(socket), Some(peer), Some(mut outgoing)) = (socket, peer, outgoing) else {
+        events.clear();
+        return;
+    };
+
+    for event in events.iter() {
+        if let ControlMessage::ResumeAck { index } = event.message {
+            if let Some(acknowledged) = outgoing.acknowledged.get_mut(index as usize) {
+                *acknowledged = true;
+            }
+        }
+    }
+
+    if outgoing.acknowledged.iter().all(|acknowledged| *acknowledged) {
+        info!("The returning player is caught up");
+        commands.insert_resource(SessionPlayers::with_local_handle(outgoing.handle, peer.0.clone()));
+        commands.remove_resource::<OutgoingResume>();
+        commands.remove_resource::<Interrupted>();
+        return;
+    }
+
+    let due = outgoing
+        .last_sent
+        .map_or(true, |last_sent| last_sent.elapsed() >= RESEND_INTERVAL);
+    if !due {
+        return;
+    }
+    outgoing.last_sent = Some(Instant::now());
+
+    let count = outgoing.chunks.len() as u32;
+    for (index, chunk) in outgoing.chunks.iter().enumerate() {
+        if !outgoing.acknowledged[index] {
+            let message = ControlMessage::ResumeChunk {
+                index: index as u32,
+                count,
+                bytes: chunk.clone(),
+            };
+            socket.send_control(&message, &peer.0);
+        }
+    }
+}
+
+/// Collects the match from the player who stayed, and takes it up once it's complete.
+fn receive_resume(
+    mut commands: Commands,
+    socket: Option<Res<SharedSocket>>,
+    peer: Option<Res<RemotePeer>>,
+    incoming: Option<ResMut<IncomingResume>>,
+    mut events: EventReader<ControlEvent>,
+) {
+    let (Some(socket), Some(peer), Some(mut incoming)) = (socket, peer, incoming) else {
+        events.clear();
+        return;
+    };
+
+    for event in events.iter() {
+        let ControlMessage::ResumeChunk { index, count, bytes } = &event.message else {
+            continue;
+        };
+        if event.peer != peer.0 {
+            continue;
+        }
+
+        // Acknowledge every copy, the acknowledgement of the first may have been lost
+        socket.send_control(&ControlMessage::ResumeAck { index: *index }, &peer.0);
+        if incoming.chunks.len() != *count as usize {
+            incoming.chunks = vec![None; *count as usize];
+        }
+        if let Some(chunk) = incoming.chunks.get_mut(*index as usize) {
+            *chunk = Some(bytes.clone());
+        }
+    }
+
+    if incoming.chunks.is_empty() || incoming.chunks.iter().any(Option::is_none) {
+        return;
+    }
+    let bytes: Vec<u8> = incoming.chunks.iter().flatten().flatten().copied().collect();
+    commands.remove_resource::<IncomingResume>();
+
+    let peer = peer.0.clone();
+    commands.add(move |world: &mut World| {
+        let registry = world.resource::<RollbackRegistry>().0.clone();
+        let resumed = bincode::deserialize::<ResumeState>(&bytes)
+            .map_err(SnapshotError::from)
+            .and_then(|state| Ok((WorldSnapshot::from_bytes(&state.snapshot, &registry)?, state)));
+        let (snapshot, state) = match resumed {
+            Ok(resumed) => resumed,
+            Err(err) => {
+                error!("Failed to resume the match: {}", err);
+                return;
+            }
+        };
+
+        snapshot.write_to_world(world, &registry);
+        fit_arena(world);
+        world.insert_resource(state.id);
+        world.insert_resource(state.series);
+        world.insert_resource(state.seed);
+        world.insert_resource(SessionPlayers::with_local_handle(1 - state.handle, peer));
+        info!("Caught up with the match at frame {}", world.resource::<FrameCount>().frame);
+    });
+}
+
+/// Stops waiting for the other player, or returning to them, when leaving the match for good.
+pub fn abandon_match(world: &mut World) {
+    world.remove_resource::<Interrupted>();
+    world.remove_resource::<Greeting>();
+    world.remove_resource::<OutgoingResume>();
+    world.remove_resource::<IncomingResume>();
+}
+
+fn update_reconnect_box(
+    interrupted: Option<Res<Interrupted>>,
+    outgoing: Option<Res<OutgoingResume>>,
+    mut box_query: Query<&mut Style, With<ReconnectBox>>,
+    mut text_query: Query<&mut Text, With<ReconnectText>>,
+) {
+    let display = if interrupted.is_some() { Display::Flex } else { Display::None };
+    for mut style in &mut box_query {
+        style.display = display;
+    }
+
+    let status = match outgoing {
+        Some(_) => "The other player is back, catching them up...",
+        None => "The other player dropped out\nWaiting for them to come back...",
+    };
+    for mut text in &mut text_query {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.to_owned();
+        }
+    }
+}
+
+/// Adds reconnecting to the app: dropped players can come back to their match, as long as the other player waits.
+pub fn build_reconnect(app: &mut App) {
+    app.add_startup_system(spawn_reconnect_box)
+        .add_system(interrupt_match)
+        .add_system(greet)
+        .add_system(send_resume.after(greet))
+        .add_system(receive_resume.after(greet))
+        .add_system(update_reconnect_box);
+}
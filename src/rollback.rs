@@ -0,0 +1,538 @@
+use bevy::{
+    prelude::*,
+    reflect::{FromType, GetTypeRegistration, TypeRegistry},
+    utils::{Duration, HashMap, Instant},
+};
+use bevy_ggrs::{
+    ggrs::{GGRSError, GGRSEvent, GGRSRequest, InputStatus, PlayerHandle, SessionState, NULL_FRAME},
+    Rollback, RollbackIdProvider, Session,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::netcode::GgrsConfig;
+use crate::simulation::FRAME_RATE;
+use crate::snapshot::{ReflectSnapshot, WorldSnapshot};
+
+
+// Rollback
+// Saves, loads and advances the rollback world as the GGRS session requests.
+// This is a fork of bevy_ggrs 0.11's `GGRSStage`. Upstream keeps the stage and everything it holds `pub(crate)`,
+// and only ever advances frames as the wall clock allows, so a headless app can't step an exact number of frames,
+// which the tests, bots and benchmarks need. The fork keeps upstream's save, load and advance handling, and adds
+// `Pacing` on top. Should bevy_ggrs make its stage steppable, this module can go back to using it.
+
+/// Stage label for the rollback stage, it runs right before `CoreStage::Update`.
+pub const ROLLBACK_UPDATE: &str = "rollback_update";
+
+// Leading peers run this much slower until the others catch up
+const RUN_SLOW_FACTOR: f64 = 1.1;
+
+/// The inputs of every player for the frame being advanced, only present while the rollback schedule runs.
+#[derive(Resource, Deref)]
+pub struct PlayerInputs(Vec<(u8, InputStatus)>);
+
+/// Counts the rollbacks of the session so far, for gauging how well the netcode hides latency.
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct RollbackStats {
+    /// Times the world was restored to an earlier frame
+    pub rollbacks: u64,
+    /// Frames simulated again after being rolled back
+    pub resimulated_frames: u64,
+}
+
+/// How the stage keeps in step with the other peers, which run on clocks of their own.
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct TimeSyncStats {
+    /// How many frames GGRS estimates we're ahead of the other peers, as of the last frame
+    pub frames_ahead: i32,
+    /// Frames advanced at the slower pace, to let the other peers catch up
+    pub slowed_frames: u64,
+    /// Frames skipped altogether, as GGRS recommended
+    pub skipped_frames: u64,
+    /// Times GGRS recommended to wait for the other peers
+    pub wait_recommendations: u64,
+}
+
+/// How far the clock has run towards the next frame, from 0 just after a frame to 1 when the next is due.
+/// Displays refreshing faster than the simulation draw what's between the last two frames by it.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FrameProgress(pub f32);
+
+impl Default for FrameProgress {
+    fn default() -> Self {
+        FrameProgress(1.0)
+    }
+}
+
+/// Where rollback entities were as of the last two frames advanced, by rollback id, for drawing what's between them.
+/// However many frames an update advances, these are the last two of them. Frames resimulated by a rollback replace
+/// the ones that were predicted.
+#[derive(Resource, Default)]
+pub struct LastFrames {
+    pub previous: HashMap<u32, Vec3>,
+    pub current: HashMap<u32, Vec3>,
+}
+
+/// Every type saved and loaded on rollback, for capturing the rollback world outside the stage.
+#[derive(Resource, Clone)]
+pub struct RollbackRegistry(pub TypeRegistry);
+
+/// An event of the GGRS session, passed on for anyone to react to.
+pub struct SessionEvent(pub GGRSEvent<GgrsConfig>);
+
+/// The rollback world as of the last frame every player's inputs were in for, kept when a player disconnects.
+/// Anything after it was predicted, so it's where a match the player returns to picks up.
+#[derive(Resource)]
+pub struct ConfirmedWorld(pub WorldSnapshot);
+
+/// How far a rollback moved an entity from where it was predicted to be, once resimulated to the same frame.
+pub struct RollbackCorrection {
+    pub entity: Entity,
+    pub delta: Vec3,
+}
+
+/// When the rollback stage advances frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// In step with the clock, at the given number of frames per second.
+    RealTime(usize),
+    /// One frame every time the stage runs, as fast as the app updates. Headless apps use this to step exact frame counts.
+    Unpaced,
+}
+
+struct RollbackStage {
+    /// The systems advancing the simulation by one frame
+    schedule: Schedule,
+    /// Every type saved and loaded on rollback
+    type_registry: TypeRegistry,
+    /// Gathers the input of local players
+    input_system: Box<dyn System<In = PlayerHandle, Out = u8>>,
+    /// Snapshots of the last frames, indexed by frame modulo their number
+    snapshots: Vec<WorldSnapshot>,
+    /// The last frame every player's inputs were in for, as of the frame being advanced
+    confirmed_frame: i32,
+    pacing: Pacing,
+    /// The frame the world is currently at
+    frame: i32,
+    last_update: Instant,
+    /// Time not yet spent on advancing frames
+    accumulator: Duration,
+    /// Whether we are ahead and should let remote clients catch up
+    run_slow: bool,
+    /// Frames left to sit out, as GGRS recommended when we got too far ahead
+    frames_to_skip: u32,
+    /// Where rollback entities were predicted to be when a rollback began, and the frame they were at
+    predicted: Option<(i32, HashMap<u32, Vec3>)>,
+}
+
+impl Stage for RollbackStage {
+    fn run(&mut self, world: &mut World) {
+        // No matter what, poll remotes and send responses
+        if let Some(mut session) = world.get_resource_mut::<Session<GgrsConfig>>() {
+            match &mut *session {
+                Session::P2PSession(session) => session.poll_remote_clients(),
+                Session::SpectatorSession(session) => session.poll_remote_clients(),
+                Session::SyncTestSession(_) => {}
+            }
+        }
+
+        // Between sessions the stage starts over, the next session counts its frames from zero again
+        if !world.contains_resource::<Session<GgrsConfig>>() {
+            self.reset();
+            *world.resource_mut::<FrameProgress>() = FrameProgress::default();
+            *world.resource_mut::<LastFrames>() = LastFrames::default();
+            world.remove_resource::<ConfirmedWorld>();
+            return;
+        }
+
+        // Events are handled as soon as polling or a frame brings them, before another frame makes them stale
+        self.handle_events(world);
+
+        for _ in 0..self.frames_due() {
+            match world.get_resource::<Session<GgrsConfig>>() {
+                Some(Session::SyncTestSession(_)) => self.run_synctest(world),
+                Some(Session::P2PSession(_)) if self.frames_to_skip > 0 => {
+                    self.frames_to_skip -= 1;
+                    world.resource_mut::<TimeSyncStats>().skipped_frames += 1;
+                }
+                Some(Session::P2PSession(_)) => self.run_p2p(world),
+                Some(Session::SpectatorSession(_)) => self.run_spectator(world),
+                None => {}
+            }
+            self.handle_events(world);
+        }
+
+        *world.resource_mut::<FrameProgress>() = self.progress();
+    }
+}
+
+impl RollbackStage {
+    /// How long a frame lasts at the current pace, unless frames aren't paced at all.
+    fn frame_time(&self) -> Option<Duration> {
+        let Pacing::RealTime(update_frequency) = self.pacing else {
+            return None;
+        };
+
+        let mut frame_time = 1.0 / update_frequency as f64;
+        if self.run_slow {
+            frame_time *= RUN_SLOW_FACTOR;
+        }
+        Some(Duration::from_secs_f64(frame_time))
+    }
+
+    fn progress(&self) -> FrameProgress {
+        match self.frame_time() {
+            Some(frame_time) => FrameProgress((self.accumulator.as_secs_f32() / frame_time.as_secs_f32()).min(1.0)),
+            None => FrameProgress::default(),
+        }
+    }
+
+    /// How many frames should be advanced this update.
+    fn frames_due(&mut self) -> u32 {
+        let Some(frame_time) = self.frame_time() else {
+            return 1;
+        };
+
+        let now = Instant::now();
+        self.accumulator = self.accumulator.saturating_add(now.duration_since(self.last_update));
+        self.last_update = now;
+
+        let mut frames = 0;
+        while self.accumulator > frame_time {
+            self.accumulator -= frame_time;
+            frames += 1;
+        }
+        frames
+    }
+
+    fn reset(&mut self) {
+        self.predicted = None;
+        self.last_update = Instant::now();
+        self.accumulator = Duration::ZERO;
+        self.frame = 0;
+        self.confirmed_frame = NULL_FRAME;
+        self.run_slow = false;
+        self.frames_to_skip = 0;
+        self.snapshots = Vec::new();
+    }
+
+    fn ensure_snapshots(&mut self, max_prediction: usize) {
+        // One more than GGRS can roll back, so the last confirmed frame is still there after the frame that's advanced
+        if self.snapshots.is_empty() {
+            self.snapshots.resize_with(max_prediction + 1, WorldSnapshot::default);
+        }
+    }
+
+    fn run_synctest(&mut self, world: &mut World) {
+        let Some(Session::SyncTestSession(session)) = world.get_resource::<Session<GgrsConfig>>() else {
+            return;
+        };
+        let num_players = session.num_players();
+        self.ensure_snapshots(session.max_prediction());
+
+        // A synctest plays every player locally
+        let inputs: Vec<u8> = (0..num_players)
+            .map(|handle| self.input_system.run(handle, world))
+            .collect();
+
+        let mut session = world.resource_mut::<Session<GgrsConfig>>();
+        let Session::SyncTestSession(session) = &mut *session else {
+            return;
+        };
+        for (handle, input) in inputs.into_iter().enumerate() {
+            session
+                .add_local_input(handle, input)
+                .expect("All handles between 0 and num_players should be valid");
+        }
+        match session.advance_frame() {
+            Ok(requests) => self.handle_requests(requests, world),
+            Err(err) => warn!("{}", err),
+        }
+    }
+
+    fn run_spectator(&mut self, world: &mut World) {
+        let mut session = world.resource_mut::<Session<GgrsConfig>>();
+        let Session::SpectatorSession(session) = &mut *session else {
+            return;
+        };
+
+        if session.current_state() == SessionState::Running {
+            match session.advance_frame() {
+                Ok(requests) => self.handle_requests(requests, world),
+                Err(GGRSError::PredictionThreshold) => info!("Waiting for input from host."),
+                Err(err) => warn!("{}", err),
+            }
+        }
+    }
+
+    fn run_p2p(&mut self, world: &mut World) {
+        let Some(Session::P2PSession(session)) = world.get_resource::<Session<GgrsConfig>>() else {
+            return;
+        };
+        self.ensure_snapshots(session.max_prediction());
+        // Until the session is running there's nothing to keep in step with, nor any frame to slow down
+        if session.current_state() != SessionState::Running {
+            self.run_slow = false;
+            return;
+        }
+        let frames_ahead = session.frames_ahead();
+        self.run_slow = frames_ahead > 0;
+
+        let local_inputs: Vec<(PlayerHandle, u8)> = session
+            .local_player_handles()
+            .into_iter()
+            .map(|handle| (handle, self.input_system.run(handle, world)))
+            .collect();
+
+        let mut time_sync = world.resource_mut::<TimeSyncStats>();
+        time_sync.frames_ahead = frames_ahead;
+        if self.run_slow {
+            time_sync.slowed_frames += 1;
+        }
+
+        let mut session = world.resource_mut::<Session<GgrsConfig>>();
+        let Session::P2PSession(session) = &mut *session else {
+            return;
+        };
+
+        for (handle, input) in local_inputs {
+            session
+                .add_local_input(handle, input)
+                .expect("All handles in local_handles should be valid");
+        }
+        // Taken before advancing, as a disconnect found while advancing stops counting the player who left
+        self.confirmed_frame = session.confirmed_frame();
+        match session.advance_frame() {
+            Ok(requests) => self.handle_requests(requests, world),
+            Err(GGRSError::PredictionThreshold) => info!("Skipping a frame: PredictionThreshold."),
+            Err(err) => warn!("{}", err),
+        }
+    }
+
+    /// Acts on the session's events and passes them on as `SessionEvent`s.
+    fn handle_events(&mut self, world: &mut World) {
+        let events: Vec<GGRSEvent<GgrsConfig>> = match world.get_resource_mut::<Session<GgrsConfig>>().as_deref_mut() {
+            Some(Session::P2PSession(session)) => session.events().collect(),
+            Some(Session::SpectatorSession(session)) => session.events().collect(),
+            _ => return,
+        };
+
+        for event in events {
+            match event {
+                GGRSEvent::WaitRecommendation { skip_frames } => {
+                    info!("Skipping {} frames to let the other peers catch up", skip_frames);
+                    self.frames_to_skip += skip_frames;
+                    world.resource_mut::<TimeSyncStats>().wait_recommendations += 1;
+                }
+                GGRSEvent::NetworkInterrupted { ref addr, disconnect_timeout } => {
+                    warn!("Lost contact with {}, disconnecting in {} ms", addr, disconnect_timeout)
+                }
+                GGRSEvent::NetworkResumed { ref addr } => info!("Back in contact with {}", addr),
+                GGRSEvent::Disconnected { ref addr } => {
+                    warn!("{} disconnected", addr);
+                    self.keep_confirmed_world(world);
+                }
+                GGRSEvent::Synchronized { ref addr } => info!("Synchronized with {}", addr),
+                GGRSEvent::Synchronizing { .. } => {}
+            }
+            world.resource_mut::<Events<SessionEvent>>().send(SessionEvent(event));
+        }
+    }
+
+    /// Hands out the snapshot of the last confirmed frame, as the session won't roll back that far anymore.
+    fn keep_confirmed_world(&mut self, world: &mut World) {
+        if self.confirmed_frame == NULL_FRAME || self.snapshots.is_empty() {
+            return;
+        }
+        let pos = self.confirmed_frame as usize % self.snapshots.len();
+        world.insert_resource(ConfirmedWorld(std::mem::take(&mut self.snapshots[pos])));
+    }
+
+    fn handle_requests(&mut self, requests: Vec<GGRSRequest<GgrsConfig>>, world: &mut World) {
+        for request in requests {
+            match request {
+                GGRSRequest::SaveGameState { cell, frame } => {
+                    debug!("saving snapshot for frame {frame}");
+                    assert_eq!(self.frame, frame);
+
+                    // We keep the snapshots ourselves and only hand GGRS the checksum
+                    let snapshot = WorldSnapshot::from_world(world, &self.type_registry);
+                    cell.save(frame, None, Some(snapshot.checksum as u128));
+                    let pos = frame as usize % self.snapshots.len();
+                    self.snapshots[pos] = snapshot;
+                }
+                GGRSRequest::LoadGameState { frame, .. } => {
+                    debug!("restoring snapshot for frame {frame}");
+                    if self.predicted.is_none() {
+                        self.predicted = Some((self.frame, rollback_translations(world)));
+                    }
+
+                    let mut stats = world.resource_mut::<RollbackStats>();
+                    stats.rollbacks += 1;
+                    stats.resimulated_frames += (self.frame - frame) as u64;
+
+                    self.frame = frame;
+                    let pos = frame as usize % self.snapshots.len();
+                    self.snapshots[pos].write_to_world(world, &self.type_registry);
+                    world.resource_mut::<LastFrames>().current = rollback_translations(world);
+                }
+                GGRSRequest::AdvanceFrame { inputs } => {
+                    debug!("advancing to frame: {}", self.frame + 1);
+                    world.insert_resource(PlayerInputs(inputs));
+                    self.schedule.run_once(world);
+                    world.remove_resource::<PlayerInputs>();
+                    self.frame += 1;
+
+                    let translations = rollback_translations(world);
+                    let mut last_frames = world.resource_mut::<LastFrames>();
+                    last_frames.previous = std::mem::replace(&mut last_frames.current, translations);
+
+                    if self.predicted.as_ref().is_some_and(|(frame, _)| *frame == self.frame) {
+                        self.send_corrections(world);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compares where entities are, having caught up with the frame they were predicted at before the rollback.
+    fn send_corrections(&mut self, world: &mut World) {
+        let Some((_, predicted)) = self.predicted.take() else {
+            return;
+        };
+
+        let corrections: Vec<RollbackCorrection> = world
+            .query::<(Entity, &Rollback, &Transform)>()
+            .iter(world)
+            .filter_map(|(entity, rollback, transform)| {
+                let delta = transform.translation - *predicted.get(&rollback.id())?;
+                (delta != Vec3::ZERO).then_some(RollbackCorrection { entity, delta })
+            })
+            .collect();
+        world.resource_mut::<Events<RollbackCorrection>>().extend(corrections);
+    }
+}
+
+/// Where every rollback entity is, by rollback id.
+fn rollback_translations(world: &mut World) -> HashMap<u32, Vec3> {
+    world
+        .query::<(&Rollback, &Transform)>()
+        .iter(world)
+        .map(|(rollback, transform)| (rollback.id(), transform.translation))
+        .collect()
+}
+
+/// Configures the rollback stage for an app, much like bevy_ggrs' `GGRSPlugin`.
+pub struct RollbackPlugin {
+    input_system: Option<Box<dyn System<In = PlayerHandle, Out = u8>>>,
+    pacing: Pacing,
+    type_registry: TypeRegistry,
+    schedule: Schedule,
+}
+
+impl Default for RollbackPlugin {
+    fn default() -> Self {
+        let type_registry = TypeRegistry::default();
+        {
+            // `Parent` and `Children` hold entities, which need mapping when rollbacks respawn them
+            let mut registry = type_registry.write();
+            registry.register::<Parent>();
+            registry.register::<Children>();
+        }
+
+        RollbackPlugin {
+            input_system: None,
+            pacing: Pacing::RealTime(FRAME_RATE),
+            type_registry,
+            schedule: Schedule::default(),
+        }
+    }
+}
+
+impl RollbackPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Changes when the rollback stage advances frames.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Registers the system returning the input of a local player, given their handle.
+    pub fn with_input_system<Params>(mut self, input_system: impl IntoSystem<PlayerHandle, u8, Params>) -> Self {
+        self.input_system = Some(Box::new(IntoSystem::into_system(input_system)));
+        self
+    }
+
+    /// Sets the schedule advancing the simulation by one frame.
+    pub fn with_rollback_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Registers a component to be saved and loaded on rollback.
+    pub fn register_rollback_component<T>(self) -> Self
+    where
+        T: GetTypeRegistration + FromReflect + Default + Component + Serialize + DeserializeOwned,
+    {
+        let mut registry = self.type_registry.write();
+        registry.register::<T>();
+        let registration = registry
+            .get_mut(std::any::TypeId::of::<T>())
+            .expect("The type was just registered");
+        registration.insert(<ReflectComponent as FromType<T>>::from_type());
+        registration.insert(<ReflectSnapshot as FromType<T>>::from_type());
+        drop(registry);
+        self
+    }
+
+    /// Registers a resource to be saved and loaded on rollback.
+    pub fn register_rollback_resource<T>(self) -> Self
+    where
+        T: GetTypeRegistration + FromReflect + Default + Resource + Serialize + DeserializeOwned,
+    {
+        let mut registry = self.type_registry.write();
+        registry.register::<T>();
+        let registration = registry
+            .get_mut(std::any::TypeId::of::<T>())
+            .expect("The type was just registered");
+        registration.insert(<ReflectResource as FromType<T>>::from_type());
+        registration.insert(<ReflectSnapshot as FromType<T>>::from_type());
+        drop(registry);
+        self
+    }
+
+    /// Adds the rollback stage to the app.
+    pub fn build(self, app: &mut App) {
+        let mut input_system = self
+            .input_system
+            .expect("An input system is required, add one with RollbackPlugin::with_input_system");
+        input_system.initialize(&mut app.world);
+
+        app.insert_resource(RollbackRegistry(self.type_registry.clone()));
+        let stage = RollbackStage {
+            schedule: self.schedule,
+            type_registry: self.type_registry,
+            input_system,
+            snapshots: Vec::new(),
+            confirmed_frame: NULL_FRAME,
+            pacing: self.pacing,
+            frame: 0,
+            last_update: Instant::now(),
+            accumulator: Duration::ZERO,
+            run_slow: false,
+            frames_to_skip: 0,
+            predicted: None,
+        };
+
+        app.add_stage_before(CoreStage::Update, ROLLBACK_UPDATE, stage)
+            .insert_resource(RollbackIdProvider::default())
+            .init_resource::<RollbackStats>()
+            .init_resource::<TimeSyncStats>()
+            .init_resource::<FrameProgress>()
+            .init_resource::<LastFrames>()
+            .add_event::<SessionEvent>()
+            .add_event::<RollbackCorrection>();
+    }
+}
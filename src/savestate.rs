@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use crate::events::clear_events;
+use crate::netcode::{FrameCount, GgrsConfig, SessionType};
+use crate::rollback::RollbackRegistry;
+use crate::simulation::fit_arena;
+use crate::snapshot::{SnapshotError, WorldSnapshot};
+use crate::stats::reset_stats;
+
+
+// Save States
+// Local matches can be saved and loaded back at any frame, which is handy for practicing a tricky shot.
+// Bug reports save the world too, in any match, so whoever looks into them can load the very same frame.
+// Natively both end up in files, which `--local --state=FILE` loads. The web has nowhere to put report files,
+// so there F8 only warns that it captured the frame.
+
+const SAVE_KEY: KeyCode = KeyCode::F5;
+const LOAD_KEY: KeyCode = KeyCode::F9;
+const REPORT_KEY: KeyCode = KeyCode::F8;
+#[cfg(not(target_arch = "wasm32"))]
+const SAVE_FILE: &str = "bifrost.state";
+
+
+/// The saved state that loading goes back to.
+#[derive(Resource, Default)]
+pub struct SaveState {
+    bytes: Option<Vec<u8>>,
+    /// Whether to load it with the next update
+    pending: bool,
+}
+
+impl SaveState {
+    /// A state to start the match from, e.g. one read from a file.
+    pub fn load(bytes: Vec<u8>) -> SaveState {
+        SaveState {
+            bytes: Some(bytes),
+            pending: true,
+        }
+    }
+}
+
+/// Serializes the rollback world as it is now.
+pub fn capture_state(world: &World) -> Result<Vec<u8>, SnapshotError> {
+    let registry = &world.resource::<RollbackRegistry>().0;
+    WorldSnapshot::from_world(world, registry).to_bytes(registry)
+}
+
+/// Puts the rollback world back the way it was serialized. The session starts over from there,
+/// any snapshots it kept are of a world that's gone, as are the events of its frames and the stats of its match.
+/// Bots start over along with the new session.
+pub fn restore_state(world: &mut World, bytes: &[u8]) -> Result<(), SnapshotError> {
+    let registry = world.resource::<RollbackRegistry>().0.clone();
+    let snapshot = WorldSnapshot::from_bytes(bytes, &registry)?;
+    world.remove_resource::<Session<GgrsConfig>>();
+    clear_events(world);
+    reset_stats(world);
+    snapshot.write_to_world(world, &registry);
+    fit_arena(world);
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_file(path: &str, bytes: &[u8]) {
+    match std::fs::write(path, bytes) {
+        Ok(()) => info!("Saved to {}", path),
+        Err(err) => warn!("Failed to write {}: {}", path, err),
+    }
+}
+
+/// F5 saves the match and F9 loads it back, in local matches only. There's nobody to agree with online.
+fn save_and_load(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    session_type: Res<SessionType>,
+    mut save_state: ResMut<SaveState>,
+) {
+    if *session_type != SessionType::Local {
+        return;
+    }
+
+    if keys.just_pressed(SAVE_KEY) {
+        commands.add(|world: &mut World| match capture_state(world) {
+            Ok(bytes) => {
+                #[cfg(not(target_arch = "wasm32"))]
+                write_file(SAVE_FILE, &bytes);
+                let frame = world.resource::<FrameCount>().frame;
+                info!("Saved the match at frame {} in {} bytes", frame, bytes.len());
+                world.insert_resource(SaveState { bytes: Some(bytes), pending: false });
+            }
+            Err(err) => error!("Failed to save the match: {}", err),
+        });
+    }
+
+    if keys.just_pressed(LOAD_KEY) {
+        save_state.pending = true;
+    }
+    if !save_state.pending {
+        return;
+    }
+    save_state.pending = false;
+    let Some(bytes) = save_state.bytes.clone() else {
+        info!("Nothing saved to load yet");
+        return;
+    };
+
+    commands.add(move |world: &mut World| match restore_state(world, &bytes) {
+        Ok(()) => info!("Loaded the match at frame {}", world.resource::<FrameCount>().frame),
+        Err(err) => error!("Failed to load the match: {}", err),
+    });
+}
+
+/// F8 saves the match as it is for a bug report.
+fn report_bug(mut commands: Commands, keys: Res<Input<KeyCode>>) {
+    if !keys.just_pressed(REPORT_KEY) {
+        return;
+    }
+
+    commands.add(|world: &mut World| {
+        let bytes = match capture_state(world) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                error!("Failed to capture the match for a bug report: {}", err);
+                return;
+            }
+        };
+        let frame = world.resource::<FrameCount>().frame;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        write_file(&format!("bifrost-report-{frame}.state"), &bytes);
+        #[cfg(target_arch = "wasm32")]
+        warn!("Captured frame {} in {} bytes, but bug reports can only be saved natively", frame, bytes.len());
+    });
+}
+
+/// Adds save states and bug reports to the app.
+pub fn build_save_states(app: &mut App) {
+    app.init_resource::<SaveState>()
+        .add_system(save_and_load)
+        .add_system(report_bug);
+}
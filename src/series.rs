@@ -0,0 +1,493 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{Duration, Instant},
+};
+use bevy_ggrs::{ggrs::PlayerHandle, Session};
+use serde::{Deserialize, Serialize};
+use crate::channels::{ControlEvent, ControlMessage, SharedSocket};
+use crate::chat::Chat;
+use crate::latency::{DelayNegotiation, InputDelaySettings};
+use crate::netcode::{FrameCount, GgrsConfig, LocalPlayerHandle, RemotePeer};
+use crate::random::Seed;
+use crate::reconnect::Interrupted;
+use crate::simulation::{reset_simulation, GameMode, Level, RoundOver, Scoreboard};
+use crate::stats::{reset_stats, MatchStats};
+
+
+// Series
+// Players play a best-of-N series of rounds over one connection, a round ends once a player's wall is broken.
+// Between rounds both games agree to go on, then each starts the rollback world over along with a fresh GGRS session,
+// so they're back in step without reconnecting. Once one player has won the series, both vote on a rematch.
+// Co-op games have nobody to win them, once the last life is lost both vote on playing again.
+
+const DEFAULT_BEST_OF: u32 = 3;
+// How long the result of a round shows before the next one starts
+const INTERMISSION: Duration = Duration::from_secs(3);
+const RESEND_INTERVAL: Duration = Duration::from_millis(250);
+const REMATCH_KEY: KeyCode = KeyCode::R;
+
+const SERIES_FONT_SIZE: f32 = 30.0;
+const RESULT_FONT_SIZE: f32 = 50.0;
+const SERIES_TEXT_PADDING: Val = Val::Px(5.0);
+const RESULT_TEXT_PADDING: Val = Val::Px(20.0);
+const SERIES_TEXT_COLOR: Color = Color::rgb(0.5, 0.5, 1.0);
+const RESULT_TEXT_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
+const RESULT_BACKGROUND_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+
+
+/// The rounds won so far by each player.
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+pub struct Series {
+    /// Rounds in the series, whoever wins more than half of them wins it
+    pub best_of: u32,
+    /// Rounds won by each player, by handle
+    pub wins: [u32; 2],
+    /// Points scored by each player over the rounds of the match so far, by handle
+    pub points: [usize; 2],
+    /// Rounds started over the connection, across rematches, so that both games know which one they're ready for
+    round: u32,
+    /// The latest round the other player is ready to start
+    peer_ready_for: u32,
+    /// The latest round counted towards the wins
+    tallied: u32,
+}
+
+impl Default for Series {
+    fn default() -> Self {
+        Series::new(DEFAULT_BEST_OF)
+    }
+}
+
+impl Series {
+    pub fn new(best_of: u32) -> Series {
+        Series {
+            best_of,
+            wins: [0; 2],
+            points: [0; 2],
+            round: 1,
+            peer_ready_for: 0,
+            tallied: 0,
+        }
+    }
+
+    /// Rounds a player has to win to take the series.
+    pub fn wins_needed(&self) -> u32 {
+        self.best_of / 2 + 1
+    }
+
+    /// The player who won the series, once someone has.
+    pub fn winner(&self) -> Option<PlayerHandle> {
+        (0..self.wins.len()).find(|handle| self.wins[*handle] >= self.wins_needed())
+    }
+
+    /// Counts the round in play towards the series: a win for `winner`, if anyone, and the points each player `scored`.
+    /// A round counts once, however often a resumed match gets to tally it.
+    pub fn tally(&mut self, winner: Option<PlayerHandle>, scored: [usize; 2]) {
+        if self.tallied >= self.round {
+            return;
+        }
+        self.tallied = self.round;
+        if let Some(winner) = winner {
+            self.wins[winner] += 1;
+        }
+        for (points, scored) in self.points.iter_mut().zip(scored) {
+            *points += scored;
+        }
+        info!("Round {} is over, the series stands at {:?}", self.round, self.wins);
+    }
+
+    /// Moves on to the next round, of a new match if `new_match`, which starts the wins and points over.
+    pub fn next_round(&mut self, new_match: bool) {
+        if new_match {
+            self.wins = [0; 2];
+            self.points = [0; 2];
+        }
+        self.round += 1;
+        info!("Starting round {}", self.round);
+    }
+
+    /// Forgets whether the other player was ready for the next round, as they have to say so again after reconnecting.
+    pub fn forget_peer_readiness(&mut self) {
+        self.peer_ready_for = 0;
+    }
+}
+
+/// How a round ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Won(PlayerHandle),
+    Draw,
+    /// A co-op game ended on the given level with the given score.
+    GameOver { level: u32, score: usize },
+}
+
+impl Outcome {
+    /// Whether the round was the last of the match: the series is won, or the co-op game lost.
+    fn ends_match(self, series: &Series) -> bool {
+        matches!(self, Outcome::GameOver { .. }) || series.winner().is_some()
+    }
+}
+
+/// How the match ended, present from the moment both games are sure it's over until the next one starts.
+#[derive(Resource, Clone, Debug)]
+pub struct MatchOver {
+    pub mode: GameMode,
+    /// Who won the series, nobody in co-op
+    pub winner: Option<PlayerHandle>,
+    /// Rounds won by each player, by handle
+    pub wins: [u32; 2],
+    /// Points scored by each player over the match, by handle
+    pub points: [usize; 2],
+    /// The team's score in co-op
+    pub team_score: Option<usize>,
+    /// The level the last round was played on
+    pub level: u32,
+}
+
+/// The pause between two rounds, present from the moment both games are sure how the round ended.
+#[derive(Resource)]
+struct Intermission {
+    outcome: Outcome,
+    started: Instant,
+    /// Whether we're ready for the next round, after the pause or after voting for a rematch
+    ready: bool,
+    last_sent: Option<Instant>,
+}
+
+#[derive(Component)]
+struct SeriesText;
+
+#[derive(Component)]
+struct ResultBox;
+
+#[derive(Component)]
+struct ResultText;
+
+fn spawn_series_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = |font_size: f32, color: Color| TextStyle {
+        font: asset_server.load("../assets/fonts/FiraSans-Bold.ttf"),
+        font_size,
+        color,
+    };
+
+    commands.spawn((
+        TextBundle::from_section("", style(SERIES_FONT_SIZE, SERIES_TEXT_COLOR)).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: SERIES_TEXT_PADDING,
+                right: SERIES_TEXT_PADDING,
+                ..default()
+            },
+            ..default()
+        }),
+        SeriesText,
+    ));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    display: Display::None,
+                    ..default()
+                },
+                ..default()
+            },
+            ResultBox,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        padding: UiRect::all(RESULT_TEXT_PADDING),
+                        ..default()
+                    },
+                    background_color: RESULT_BACKGROUND_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section("", style(RESULT_FONT_SIZE, RESULT_TEXT_COLOR))
+                            .with_text_alignment(TextAlignment::CENTER),
+                        ResultText,
+                    ));
+                });
+        });
+}
+
+/// A new connection starts a new series, unless it resumes an interrupted match.
+fn reset_series(peer: Option<Res<RemotePeer>>, interrupted: Option<Res<Interrupted>>, mut series: ResMut<Series>) {
+    if peer.is_some_and(|peer| peer.is_added()) && interrupted.is_none() {
+        *series = Series::new(series.best_of);
+    }
+}
+
+/// What the simulation says about how the round went.
+#[derive(SystemParam)]
+struct RoundResult<'w, 's> {
+    mode: Res<'w, GameMode>,
+    round_over: Res<'w, RoundOver>,
+    scoreboard: Res<'w, Scoreboard>,
+    level: Res<'w, Level>,
+    #[system_param(ignore)]
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl RoundResult<'_, '_> {
+    fn outcome(&self) -> Outcome {
+        match (*self.mode, self.round_over.winner) {
+            (GameMode::Versus, Some(winner)) => Outcome::Won(winner),
+            (GameMode::Versus, None) => Outcome::Draw,
+            (GameMode::CoOp, _) => Outcome::GameOver {
+                level: self.level.number,
+                score: self.scoreboard.score,
+            },
+        }
+    }
+}
+
+/// Tallies the round once it's over for sure, that is once the frame it ended on is confirmed and can't be rolled back.
+fn settle_round(
+    mut commands: Commands,
+    session: Option<Res<Session<GgrsConfig>>>,
+    frame_count: Res<FrameCount>,
+    result: RoundResult,
+    intermission: Option<Res<Intermission>>,
+    mut series: ResMut<Series>,
+) {
+    let (Some(Session::P2PSession(session)), Some(frame), None) = (session.as_deref(), result.round_over.frame, intermission) else {
+        return;
+    };
+    // A resumed match counts its frames on from where it was, while its session counts from zero
+    let unconfirmed_frames = session.current_frame() - session.confirmed_frame();
+    if (frame_count.frame as i32) - unconfirmed_frames < frame as i32 {
+        return;
+    }
+
+    let outcome = result.outcome();
+    let winner = match outcome {
+        Outcome::Won(winner) => Some(winner),
+        Outcome::Draw | Outcome::GameOver { .. } => None,
+    };
+    // A resumed match may have been tallied before it was interrupted
+    series.tally(winner, result.scoreboard.players);
+    if outcome.ends_match(&series) {
+        commands.insert_resource(MatchOver {
+            mode: *result.mode,
+            winner: series.winner(),
+            wins: series.wins,
+            points: series.points,
+            team_score: match outcome {
+                Outcome::GameOver { score, .. } => Some(score),
+                _ => None,
+            },
+            level: result.level.number,
+        });
+    }
+
+    commands.insert_resource(Intermission {
+        outcome,
+        started: Instant::now(),
+        ready: false,
+        last_sent: None,
+    });
+}
+
+/// Gets ready for the next round once the result has been on screen for a while, or after voting for a rematch
+/// once the match is over.
+fn get_ready(
+    keys: Res<Input<KeyCode>>,
+    chat: Option<Res<Chat>>,
+    series: Res<Series>,
+    intermission: Option<ResMut<Intermission>>,
+) {
+    let Some(mut intermission) = intermission else {
+        return;
+    };
+    if intermission.ready {
+        return;
+    }
+
+    let typing = chat.is_some_and(|chat| chat.is_typing());
+    intermission.ready = match intermission.outcome.ends_match(&series) {
+        true => keys.just_pressed(REMATCH_KEY) && !typing,
+        false => intermission.started.elapsed() >= INTERMISSION,
+    };
+}
+
+/// Tells the other player which round we're ready for until they follow, and hears which they're ready for.
+fn exchange_readiness(
+    socket: Option<Res<SharedSocket>>,
+    peer: Option<Res<RemotePeer>>,
+    mut events: EventReader<ControlEvent>,
+    mut series: ResMut<Series>,
+    intermission: Option<ResMut<Intermission>>,
+) {
+    let (Some(socket), Some(peer)) = (socket, peer) else {
+        events.clear();
+        return;
+    };
+
+    for event in events.iter() {
+        let ControlMessage::NextRound { round } = event.message else {
+            continue;
+        };
+        if event.peer != peer.0 {
+            continue;
+        }
+
+        if round <= series.round {
+            // We've started that round already, so they just haven't heard we were ready
+            socket.send_control(&ControlMessage::NextRound { round }, &peer.0);
+        } else {
+            series.peer_ready_for = series.peer_ready_for.max(round);
+        }
+    }
+
+    let Some(mut intermission) = intermission else {
+        return;
+    };
+    let due = intermission
+        .last_sent
+        .map_or(true, |last_sent| last_sent.elapsed() >= RESEND_INTERVAL);
+    if intermission.ready && due {
+        intermission.last_sent = Some(Instant::now());
+        socket.send_control(&ControlMessage::NextRound { round: series.round + 1 }, &peer.0);
+    }
+}
+
+/// Starts the next round once both players are ready for it, ending the session of the last one.
+/// The world resets at the end of this update, and the rollback stage sees there's no session during the next,
+/// before a new session starts on the same socket.
+fn start_next_round(
+    mut commands: Commands,
+    peer: Option<Res<RemotePeer>>,
+    interrupted: Option<Res<Interrupted>>,
+    intermission: Option<Res<Intermission>>,
+    mut series: ResMut<Series>,
+    negotiation: Option<ResMut<DelayNegotiation>>,
+    settings: Res<InputDelaySettings>,
+) {
+    let Some(intermission) = intermission else {
+        return;
+    };
+    // Nobody to play the next round with until the other player is back
+    if interrupted.is_some() {
+        return;
+    }
+    let peer_ready = peer.is_none() || series.peer_ready_for > series.round;
+    if !intermission.ready || !peer_ready {
+        return;
+    }
+
+    let new_match = intermission.outcome.ends_match(&series);
+    series.next_round(new_match);
+
+    commands.remove_resource::<Intermission>();
+    commands.remove_resource::<MatchOver>();
+    commands.remove_resource::<Session<GgrsConfig>>();
+    commands.add(move |world: &mut World| {
+        world.resource_mut::<Seed>().advance();
+        reset_simulation(world);
+        match new_match {
+            true => reset_stats(world),
+            false => world.resource_mut::<MatchStats>().next_round(),
+        }
+    });
+
+    if let (true, Some(mut negotiation)) = (settings.renegotiate, negotiation) {
+        negotiation.restart();
+    }
+}
+
+/// Forgets the series once its players part ways, whoever plays next starts a series of their own.
+pub fn end_series(world: &mut World) {
+    world.remove_resource::<Intermission>();
+    world.remove_resource::<MatchOver>();
+    reset_stats(world);
+    let best_of = world.resource::<Series>().best_of;
+    world.insert_resource(Series::new(best_of));
+}
+
+/// How a player is called on screen.
+pub fn player_name(handle: PlayerHandle) -> String {
+    format!("Player {}", handle + 1)
+}
+
+/// Co-op games aren't won by anyone, so there's no series to show.
+fn update_series_text(series: Res<Series>, mode: Res<GameMode>, mut query: Query<&mut Text, With<SeriesText>>) {
+    let value = match *mode {
+        GameMode::Versus => format!(
+            "Series {} - {}, first to {}",
+            series.wins[0],
+            series.wins[1],
+            series.wins_needed()
+        ),
+        GameMode::CoOp => String::new(),
+    };
+    for mut text in &mut query {
+        text.sections[0].value.clone_from(&value);
+    }
+}
+
+fn update_result_box(
+    series: Res<Series>,
+    intermission: Option<Res<Intermission>>,
+    peer: Option<Res<RemotePeer>>,
+    local_handle: Option<Res<LocalPlayerHandle>>,
+    mut result_query: Query<&mut Text, With<ResultText>>,
+    mut box_query: Query<&mut Style, With<ResultBox>>,
+) {
+    let display = if intermission.is_some() { Display::Flex } else { Display::None };
+    for mut style in &mut box_query {
+        style.display = display;
+    }
+    let Some(intermission) = intermission else {
+        return;
+    };
+
+    let mut result = match intermission.outcome {
+        Outcome::Won(winner) => format!("{} wins the round", player_name(winner)),
+        Outcome::Draw => "The round is a draw".to_owned(),
+        Outcome::GameOver { level, score } => format!("Game over on level {level}, with {score} bricks broken"),
+    };
+    if let Some(local_handle) = local_handle.filter(|_| peer.is_some()) {
+        result += &format!("\nYou are {}", player_name(local_handle.0));
+    }
+
+    let peer_ready = peer.is_none() || series.peer_ready_for > series.round;
+    let headline = match series.winner() {
+        Some(winner) => format!("{} wins the series!\n", player_name(winner)),
+        None => String::new(),
+    };
+    let status = match (intermission.outcome.ends_match(&series), intermission.ready, peer_ready) {
+        (true, false, true) if peer.is_some() => format!("{headline}The other player wants a rematch, R to accept"),
+        (true, false, _) => format!("{headline}R for a rematch"),
+        (_, true, false) => "Waiting for the other player...".to_owned(),
+        _ => {
+            let remaining = INTERMISSION.saturating_sub(intermission.started.elapsed());
+            format!("Next round in {}", remaining.as_secs() + 1)
+        }
+    };
+
+    for mut text in &mut result_query {
+        text.sections[0].value = format!("{result}\n{status}");
+    }
+}
+
+/// Adds best-of-N series to the app, with the next round or a rematch starting once both players are ready.
+pub fn build_series(app: &mut App) {
+    app.init_resource::<Series>()
+        .add_startup_system(spawn_series_ui)
+        .add_system(reset_series)
+        .add_system(settle_round.after(reset_series))
+        .add_system(get_ready.after(settle_round))
+        .add_system(exchange_readiness.after(get_ready))
+        .add_system(start_next_round.after(exchange_readiness))
+        .add_system(update_series_text.after(start_next_round))
+        .add_system(update_result_box.after(start_next_round));
+}
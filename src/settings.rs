@@ -0,0 +1,215 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use crate::game::AppState;
+use crate::input::Controls;
+use crate::latency::InputDelaySettings;
+use crate::netcode::ModeChoice;
+use crate::ui::*;
+
+
+// Settings
+// The game mode, sound, controls and network options, changed on a screen of their own from the main menu or during a match.
+// The mode is that of the next match, local or online.
+// Each setting is a row of arrows around its current value.
+
+const VOLUME_STEP: f32 = 0.1;
+// The most frames of input delay to pick, beyond this rollbacks hide latency better
+const MAX_INPUT_DELAY: usize = 10;
+const BACK_KEY: KeyCode = KeyCode::Escape;
+
+const SETTING_NAME_WIDTH: Val = Val::Px(280.0);
+const SETTING_VALUE_WIDTH: Val = Val::Px(140.0);
+
+
+/// How loud the game plays its sounds, from 0 to 1.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct Volume(pub f32);
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume(1.0)
+    }
+}
+
+/// A setting with a row of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Setting {
+    Mode,
+    Volume,
+    PlayerKeys(usize),
+    MinInputDelay,
+    MaxInputDelay,
+    Renegotiate,
+}
+
+impl Setting {
+    const ALL: [Setting; 7] = [
+        Setting::Mode,
+        Setting::Volume,
+        Setting::PlayerKeys(0),
+        Setting::PlayerKeys(1),
+        Setting::MinInputDelay,
+        Setting::MaxInputDelay,
+        Setting::Renegotiate,
+    ];
+
+    fn name(self) -> String {
+        match self {
+            Setting::Mode => "Game mode".to_owned(),
+            Setting::Volume => "Volume".to_owned(),
+            Setting::PlayerKeys(handle) => format!("Player {} keys", handle + 1),
+            Setting::MinInputDelay => "Min input delay".to_owned(),
+            Setting::MaxInputDelay => "Max input delay".to_owned(),
+            Setting::Renegotiate => "Measure every round".to_owned(),
+        }
+    }
+}
+
+/// Everything the settings change.
+#[derive(SystemParam)]
+struct SettingValues<'w, 's> {
+    mode_choice: ResMut<'w, ModeChoice>,
+    volume: ResMut<'w, Volume>,
+    controls: ResMut<'w, Controls>,
+    input_delay: ResMut<'w, InputDelaySettings>,
+    #[system_param(ignore)]
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl SettingValues<'_, '_> {
+    fn value(&self, setting: Setting) -> String {
+        match setting {
+            Setting::Mode => self.mode_choice.mode.name().to_owned(),
+            Setting::Volume => format!("{}%", (self.volume.0 * 100.0).round()),
+            Setting::PlayerKeys(handle) => self.controls.players[handle].name().to_owned(),
+            Setting::MinInputDelay => format!("{} frames", self.input_delay.min),
+            Setting::MaxInputDelay => format!("{} frames", self.input_delay.max),
+            Setting::Renegotiate if self.input_delay.renegotiate => "On".to_owned(),
+            Setting::Renegotiate => "Off".to_owned(),
+        }
+    }
+
+    /// Moves the setting `steps` up or down from where it is.
+    fn change(&mut self, setting: Setting, steps: i32) {
+        match setting {
+            Setting::Mode => self.mode_choice.mode = self.mode_choice.mode.other(),
+            Setting::Volume => {
+                let volume = self.volume.0 + steps as f32 * VOLUME_STEP;
+                // Steps of a tenth add up to slightly off values otherwise
+                self.volume.0 = (volume.clamp(0.0, 1.0) / VOLUME_STEP).round() * VOLUME_STEP;
+            }
+            Setting::PlayerKeys(handle) => {
+                let keys = &mut self.controls.players[handle];
+                *keys = keys.cycle(steps);
+            }
+            Setting::MinInputDelay => {
+                let min = self.input_delay.min as i32 + steps;
+                self.input_delay.min = min.clamp(0, self.input_delay.max as i32) as usize;
+            }
+            Setting::MaxInputDelay => {
+                let max = self.input_delay.max as i32 + steps;
+                self.input_delay.max = max.clamp(self.input_delay.min as i32, MAX_INPUT_DELAY as i32) as usize;
+            }
+            Setting::Renegotiate => self.input_delay.renegotiate = !self.input_delay.renegotiate,
+        }
+    }
+}
+
+/// Changes a setting by a step when clicked.
+#[derive(Component)]
+struct SettingButton {
+    setting: Setting,
+    steps: i32,
+}
+
+#[derive(Component)]
+struct SettingValueText(Setting);
+
+/// Everything on the settings screen, despawned when leaving it.
+#[derive(Component)]
+struct SettingsUi;
+
+#[derive(Component)]
+struct BackButton;
+
+/// Spawns a row for every setting, for a screen or overlay to show.
+pub fn spawn_settings(parent: &mut ChildBuilder, font: &Handle<Font>) {
+    let fixed_width = |width: Val, text: TextBundle| {
+        text.with_style(Style {
+            size: Size::new(width, Val::Auto),
+            ..default()
+        })
+    };
+
+    parent.spawn(column()).with_children(|parent| {
+        for setting in Setting::ALL {
+            parent.spawn(row()).with_children(|parent| {
+                parent.spawn(fixed_width(
+                    SETTING_NAME_WIDTH,
+                    ui_text(setting.name(), font, UI_FONT_SIZE, UI_TEXT_COLOR),
+                ));
+                spawn_button(parent, font, "<", SMALL_BUTTON_SIZE, SettingButton { setting, steps: -1 });
+                parent.spawn((
+                    fixed_width(SETTING_VALUE_WIDTH, ui_text("", font, UI_FONT_SIZE, UI_TEXT_COLOR))
+                        .with_text_alignment(TextAlignment::CENTER),
+                    SettingValueText(setting),
+                ));
+                spawn_button(parent, font, ">", SMALL_BUTTON_SIZE, SettingButton { setting, steps: 1 });
+            });
+        }
+    });
+}
+
+fn click_setting_buttons(query: Query<(&Interaction, &SettingButton), Changed<Interaction>>, mut values: SettingValues) {
+    for (interaction, button) in &query {
+        if *interaction == Interaction::Clicked {
+            values.change(button.setting, button.steps);
+        }
+    }
+}
+
+fn update_setting_values(values: SettingValues, mut query: Query<(&mut Text, &SettingValueText)>) {
+    for (mut text, value_text) in &mut query {
+        let value = values.value(value_text.0);
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+fn spawn_settings_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = ui_font(&asset_server);
+    commands.spawn((screen(), SettingsUi)).with_children(|parent| {
+        parent.spawn(ui_text("Settings", &font, TITLE_FONT_SIZE, UI_TEXT_COLOR));
+        spawn_settings(parent, &font);
+        spawn_button(parent, &font, "Back", BUTTON_SIZE, BackButton);
+    });
+}
+
+fn despawn_settings_screen(mut commands: Commands, query: Query<Entity, With<SettingsUi>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Back goes back to the main menu, as does Escape.
+fn leave_settings_screen(
+    keys: Res<Input<KeyCode>>,
+    query: Query<&Interaction, (Changed<Interaction>, With<BackButton>)>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(BACK_KEY) || query.iter().any(|interaction| *interaction == Interaction::Clicked) {
+        state.set(AppState::Menu).ok();
+    }
+}
+
+/// Adds the settings to the app, with a screen of their own while in `AppState::Settings`.
+/// Their rows work wherever `spawn_settings` puts them.
+pub fn build_settings(app: &mut App) {
+    app.init_resource::<Volume>()
+        .init_resource::<Controls>()
+        .add_system(click_setting_buttons)
+        .add_system(update_setting_values.after(click_setting_buttons))
+        .add_system_set(SystemSet::on_enter(AppState::Settings).with_system(spawn_settings_screen))
+        .add_system_set(SystemSet::on_update(AppState::Settings).with_system(leave_settings_screen))
+        .add_system_set(SystemSet::on_exit(AppState::Settings).with_system(despawn_settings_screen));
+}
@@ -0,0 +1,1019 @@
+use bevy::{
+    ecs::{
+        schedule::ShouldRun,
+        system::{CommandQueue, SystemParam},
+    },
+    math::Rect,
+    prelude::*,
+    sprite::collide_aabb::{collide, Collision},
+};
+use bevy_ggrs::{ggrs::PlayerHandle, Rollback, RollbackIdProvider};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use crate::components::*;
+use crate::events::*;
+use crate::input::*;
+use crate::netcode::*;
+use crate::random::{RollbackRng, Seed};
+use crate::rollback::*;
+
+
+// Simulation
+// The deterministic part of Breakout, everything GGRS saves, loads and advances.
+// It doesn't draw or play anything, so it runs just as well headless with `MinimalPlugins`.
+
+// The simulation advances `FrameRate` frames per second, whatever the display refreshes at, this many by default.
+// Speeds are given per second and durations in seconds, each frame moves things along by one time step of them.
+pub const FRAME_RATE: usize = 60;
+// The frame rates a game can be launched with
+pub const MIN_FRAME_RATE: usize = 30;
+pub const MAX_FRAME_RATE: usize = 240;
+
+// Player defaults
+pub const PLAYER_MOVE_SPEED: f32 = 420.0;
+pub const P1_START_POSITION: Vec3 = Vec3::new(-200.0, BOTTOM_WALL + GAP_BETWEEN_PADDLE_AND_FLOOR, 0.0);
+pub const P2_START_POSITION: Vec3 = Vec3::new(200.0, BOTTOM_WALL + GAP_BETWEEN_PADDLE_AND_FLOOR, 0.0);
+
+// Paddle
+pub const PADDLE_SIZE: Vec3 = Vec3::new(120.0, 20.0, 0.0);
+const GAP_BETWEEN_PADDLE_AND_FLOOR: f32 = 60.0;
+// How close can the paddle get to the wall
+const PADDLE_PADDING: f32 = 10.0;
+
+// Ball
+// We set the z-value of the ball to 1 so it renders on top in the case of overlapping sprites.
+pub const BALL_STARTING_POSITION: Vec3 = Vec3::new(0.0, -50.0, 1.0);
+pub const BALL_SIZE: Vec3 = Vec3::new(30.0, 30.0, 0.0);
+pub const BALL_SPEED: f32 = 400.0;
+// The ball is served downwards, drifting sideways by a random slope within these
+const SERVE_MIN_SLOPE: f32 = 0.4;
+const SERVE_MAX_SLOPE: f32 = 1.0;
+
+// Ball speed
+// The ball speeds up by a level's step every few hits of a paddle or brick, up to the level's cap, and is served at
+// the level's speed again once it's lost. Co-op levels past the last play its speeds, versus plays the first level's
+pub const HITS_PER_SPEED_UP: u32 = 4;
+pub const LEVEL_SPEEDS: [LevelSpeed; 3] = [
+    LevelSpeed { serve: BALL_SPEED, step: 25.0, max: 650.0 },
+    LevelSpeed { serve: 440.0, step: 30.0, max: 700.0 },
+    LevelSpeed { serve: 480.0, step: 35.0, max: 750.0 },
+];
+
+// Walls
+pub const WALL_THICKNESS: f32 = 10.0;
+// x coordinates
+pub const LEFT_WALL: f32 = -450.0;
+pub const RIGHT_WALL: f32 = 450.0;
+// y coordinates
+pub const BOTTOM_WALL: f32 = -300.0;
+pub const TOP_WALL: f32 = 300.0;
+pub const DIVIDER_WALL: f32 = 0.0;
+
+// Bricks
+pub const BRICK_SIZE: Vec2 = Vec2::new(100.0, 30.0);
+const GAP_BETWEEN_PADDLE_AND_BRICKS: f32 = 270.0;
+const GAP_BETWEEN_BRICKS: f32 = 5.0;
+const GAP_BETWEEN_BRICKS_AND_CEILING: f32 = 20.0;
+const GAP_BETWEEN_BRICKS_AND_SIDES: f32 = 20.0;
+// One in this many bricks is left out of a round's wall, mirrored on both sides so neither player is favored
+const MISSING_BRICK_ODDS: u32 = 6;
+
+// Versus
+// The first player defends the bottom of the arena and the second the top, each with a wall of their own in front of their goal
+const VERSUS_BRICK_ROWS: usize = 2;
+const GAP_BETWEEN_BRICKS_AND_GOAL: f32 = 15.0;
+const GAP_BETWEEN_BRICKS_AND_PADDLE: f32 = 40.0;
+// y coordinate of the top of the first player's wall, the second player's is mirrored across the divider
+const VERSUS_WALL_EDGE: f32 = BOTTOM_WALL
+    + WALL_THICKNESS / 2.0
+    + GAP_BETWEEN_BRICKS_AND_GOAL
+    + VERSUS_BRICK_ROWS as f32 * (BRICK_SIZE.y + GAP_BETWEEN_BRICKS);
+
+// Power-ups
+// One in this many bricks drops a power-up when broken, which widens the paddle that catches it for a while
+const POWER_UP_ODDS: u32 = 6;
+pub const POWER_UP_SIZE: Vec3 = Vec3::new(40.0, 15.0, 1.0);
+const POWER_UP_SPEED: f32 = 150.0;
+const WIDENED_PADDLE_SCALE: f32 = 1.5;
+const WIDENED_SECONDS: u32 = 10;
+
+// Balls lost
+// A ball that gets past the paddles costs the team one of its lives in co-op, and scores for the other player in versus
+const STARTING_LIVES: u32 = 3;
+const MISSED_BALL_POINTS: usize = 3;
+
+
+/// How fast the ball goes on a level, in units per second.
+pub struct LevelSpeed {
+    /// When it's served
+    pub serve: f32,
+    /// How much faster it gets every `HITS_PER_SPEED_UP` hits
+    pub step: f32,
+    pub max: f32,
+}
+
+impl LevelSpeed {
+    fn of(level: &Level) -> &'static LevelSpeed {
+        let index = (level.number.max(1) - 1) as usize;
+        &LEVEL_SPEEDS[index.min(LEVEL_SPEEDS.len() - 1)]
+    }
+
+    /// The speed of a ball that hit `hits` paddles and bricks since it was served.
+    fn after(&self, hits: u32) -> f32 {
+        (self.serve + (hits / HITS_PER_SPEED_UP) as f32 * self.step).min(self.max)
+    }
+}
+
+/// How many frames the simulation advances per second. Frames are simulated one time step at a time,
+/// so the peers of a match have to run at the same rate to stay in step.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRate(pub usize);
+
+impl Default for FrameRate {
+    fn default() -> Self {
+        FrameRate(FRAME_RATE)
+    }
+}
+
+impl FrameRate {
+    /// The frame rate to launch with, if it's one the game can run at.
+    pub fn new(frame_rate: usize) -> Result<FrameRate, String> {
+        if !(MIN_FRAME_RATE..=MAX_FRAME_RATE).contains(&frame_rate) {
+            return Err(format!(
+                "Invalid frame_rate {frame_rate}: it needs to be {MIN_FRAME_RATE} to {MAX_FRAME_RATE} frames per second"
+            ));
+        }
+        Ok(FrameRate(frame_rate))
+    }
+
+    /// Seconds a frame lasts.
+    pub fn time_step(self) -> f32 {
+        1.0 / self.0 as f32
+    }
+
+    /// Frames lasting the given number of seconds.
+    pub fn frames(self, seconds: u32) -> u32 {
+        seconds * self.0 as u32
+    }
+}
+
+/// How the players play together. Agreed on before the match starts, and the same throughout it.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Hash)]
+pub enum GameMode {
+    /// Each player defends their own wall and goal, whoever breaks the other's wall first wins the round.
+    #[default]
+    Versus,
+    /// The players share a score and lives, clearing one wall after another until the last life is lost.
+    CoOp,
+}
+
+impl GameMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Versus => "Versus",
+            GameMode::CoOp => "Co-op",
+        }
+    }
+
+    /// The other mode, to switch between them.
+    pub fn other(self) -> GameMode {
+        match self {
+            GameMode::Versus => GameMode::CoOp,
+            GameMode::CoOp => GameMode::Versus,
+        }
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "versus" => Ok(GameMode::Versus),
+            "coop" => Ok(GameMode::CoOp),
+            _ => Err(format!("Unknown mode: {s}")),
+        }
+    }
+}
+
+/// The wall being played in co-op, and where the rollback ids of the bricks start.
+/// Ids are handed out for a full wall when the round starts and every level's wall reuses them,
+/// so bricks spawned in the middle of a round are numbered the same on every peer, however often it's resimulated.
+#[derive(Resource, Default, Reflect, FromReflect, Hash, Serialize, Deserialize)]
+#[reflect(Hash)]
+pub struct Level {
+    pub number: u32,
+    first_brick_id: u32,
+}
+
+// This resource tracks the game's score
+#[derive(Resource, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct Scoreboard {
+    pub score: usize,
+    /// Points of each player by handle: the bricks they broke, and in versus the balls that got into the other player's goal
+    pub players: [usize; 2],
+    /// Balls the team can still lose in co-op
+    pub lives: u32,
+}
+
+impl Default for Scoreboard {
+    fn default() -> Self {
+        Scoreboard {
+            score: 0,
+            players: [0; 2],
+            lives: STARTING_LIVES,
+        }
+    }
+}
+
+/// Whether the round is over: in versus once a player's wall is broken, in co-op once the last life is lost.
+/// Nothing moves from then on.
+#[derive(Resource, Default, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct RoundOver {
+    /// The frame the round ended on
+    pub frame: Option<u32>,
+    /// Who won the round in versus, by breaking the other player's wall. Nobody if both walls fell together
+    pub winner: Option<PlayerHandle>,
+}
+
+/// Which side of the arena is this wall located on?
+pub enum WallLocation {
+    Left,
+    Right,
+    Bottom,
+    Top,
+    Center
+}
+
+impl WallLocation {
+    pub fn position(&self) -> Vec2 {
+        match self {
+            WallLocation::Left => Vec2::new(LEFT_WALL, 0.),
+            WallLocation::Right => Vec2::new(RIGHT_WALL, 0.),
+            WallLocation::Bottom => Vec2::new(0., BOTTOM_WALL),
+            WallLocation::Top => Vec2::new(0., TOP_WALL),
+            WallLocation::Center => Vec2::new(0., DIVIDER_WALL),
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        let arena_height = TOP_WALL - BOTTOM_WALL;
+        let arena_width = RIGHT_WALL - LEFT_WALL;
+        // Make sure we haven't messed up our constants
+        assert!(arena_height > 0.0);
+        assert!(arena_width > 0.0);
+
+        match self {
+            WallLocation::Left | WallLocation::Right => {
+                Vec2::new(WALL_THICKNESS, arena_height + WALL_THICKNESS)
+            }
+            WallLocation::Bottom | WallLocation::Top => {
+                Vec2::new(arena_width + WALL_THICKNESS, WALL_THICKNESS)
+            }
+            WallLocation::Center => {
+                Vec2::new(arena_width -5., WALL_THICKNESS)
+            }
+        }
+    }
+
+    /// Where and how big the wall is, as a transform.
+    pub fn transform(&self) -> Transform {
+        Transform {
+            // We need to convert our Vec2 into a Vec3, by giving it a z-coordinate
+            // This is used to determine the order of our sprites
+            translation: self.position().extend(0.0),
+            // The z-scale of 2D objects must always be 1.0,
+            // or their ordering will be affected in surprising ways.
+            // See https://github.com/bevyengine/bevy/issues/4149
+            scale: self.size().extend(1.0),
+            ..default()
+        }
+    }
+}
+
+/// The part of the arena a player's paddle moves within, on their side of the divider.
+/// In versus that's between their own wall and the divider, with the second player's side mirroring the first's.
+pub fn paddle_zone(mode: GameMode, handle: PlayerHandle) -> Rect {
+    let bottom = match mode {
+        GameMode::Versus => VERSUS_WALL_EDGE,
+        GameMode::CoOp => BOTTOM_WALL + WALL_THICKNESS / 2.0,
+    };
+    let zone = Rect {
+        min: Vec2::new(LEFT_WALL + WALL_THICKNESS / 2.0, bottom),
+        max: Vec2::new(RIGHT_WALL - WALL_THICKNESS / 2.0, DIVIDER_WALL - WALL_THICKNESS / 2.0),
+    };
+    match (mode, handle) {
+        (GameMode::Versus, 1) => Rect {
+            min: Vec2::new(zone.min.x, mirrored(zone.max.extend(0.0)).y),
+            max: Vec2::new(zone.max.x, mirrored(zone.min.extend(0.0)).y),
+        },
+        _ => zone,
+    }
+}
+
+/// Where a paddle starts the round.
+fn start_position(mode: GameMode, handle: PlayerHandle) -> Vec3 {
+    match (mode, handle) {
+        (GameMode::Versus, _) => {
+            let bottom = Vec3::new(0.0, VERSUS_WALL_EDGE + GAP_BETWEEN_BRICKS_AND_PADDLE + PADDLE_SIZE.y / 2.0, 0.0);
+            if handle == 0 { bottom } else { mirrored(bottom) }
+        }
+        (GameMode::CoOp, 0) => P1_START_POSITION,
+        (GameMode::CoOp, _) => P2_START_POSITION,
+    }
+}
+
+/// The point on the other side of the divider, as far from it.
+fn mirrored(point: Vec3) -> Vec3 {
+    Vec3::new(point.x, 2.0 * DIVIDER_WALL - point.y, point.z)
+}
+
+/// Which way a player's goal is, along y: down for the first player, up for the second.
+fn towards_goal(handle: PlayerHandle) -> f32 {
+    if handle == 0 { -1.0 } else { 1.0 }
+}
+
+#[derive(Bundle)]
+struct WallBundle {
+    transform: Transform,
+    wall: Wall,
+    collider: Collider,
+}
+
+impl WallBundle {
+    // This "builder method" allows us to reuse logic across our wall entities,
+    // making our code easier to read and less prone to bugs when we change the logic
+    fn new(location: WallLocation) -> WallBundle {
+        WallBundle {
+            transform: location.transform(),
+            wall: Wall,
+            collider: Collider,
+        }
+    }
+}
+
+fn apply_velocity(frame_rate: Res<FrameRate>, mut query: Query<(&mut Transform, &Velocity)>) {
+    let time_step = frame_rate.time_step();
+    for (mut transform, velocity) in &mut query {
+        transform.translation.x += velocity.x * time_step;
+        transform.translation.y += velocity.y * time_step;
+    }
+}
+
+fn check_for_collisions(
+    mut commands: Commands,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut events: ResMut<FrameEvents>,
+    mut ball_query: Query<(&mut Ball, &mut Velocity, &Transform)>,
+    collider_query: Query<(Entity, &Transform, Option<&Brick>), With<Collider>>,
+    player_query: Query<&Player>,
+    rollback_query: Query<&Rollback>,
+) {
+    let (mut ball, mut ball_velocity, ball_transform) = ball_query.single_mut();
+    let ball_size = ball_transform.scale.truncate();
+
+    // Check wall collision
+    for (collider_entity, transform, maybe_brick) in &collider_query {
+        let collision = collide(
+            ball_transform.translation,
+            ball_size,
+            transform.translation,
+            transform.scale.truncate(),
+        );
+
+        if let Some(collision) = collision {
+            // Bricks should be despawned and increment the scoreboard on collision
+            if let Some(brick) = maybe_brick {
+                ball.hits += 1;
+                // A versus wall counts for the player attacking it, whoever touched the ball last
+                let breaker = match brick.owner {
+                    Some(owner) => Some(1 - owner),
+                    None => ball.last_hit_by,
+                };
+                events.send(SimulationEvent::BrickDestroyed {
+                    id: rollback_query.get(collider_entity).expect("Bricks are rolled back").id(),
+                    by_player: breaker,
+                    owner: brick.owner,
+                    position: transform.translation.truncate(),
+                });
+                scoreboard.score += 1;
+                if let Some(handle) = breaker {
+                    scoreboard.players[handle] += 1;
+                }
+                // The brick turns into the power-up it drops, which keeps it under the same rollback id
+                if brick.power_up {
+                    let for_player = brick.owner.and(breaker);
+                    commands
+                        .entity(collider_entity)
+                        .remove::<(Brick, Collider)>()
+                        .insert((
+                            PowerUp { for_player },
+                            Transform::from_translation(transform.translation).with_scale(POWER_UP_SIZE),
+                            Velocity(Vec2::new(0.0, for_player.map_or(-1.0, towards_goal) * POWER_UP_SPEED)),
+                        ));
+                } else {
+                    commands.entity(collider_entity).despawn();
+                }
+            }
+
+            if let Ok(player) = player_query.get(collider_entity) {
+                ball.last_hit_by = Some(player.handle);
+            }
+
+            // Reflect the ball when it collides
+            let mut reflect_x = false;
+            let mut reflect_y = false;
+
+            // Only reflect if the ball's velocity is going in the opposite direction of the collision
+            match collision {
+                Collision::Left => reflect_x = ball_velocity.x > 0.0,
+                Collision::Right => reflect_x = ball_velocity.x < 0.0,
+                Collision::Top => reflect_y = ball_velocity.y < 0.0,
+                Collision::Bottom => reflect_y = ball_velocity.y > 0.0,
+                Collision::Inside => { /* do nothing */ }
+            }
+
+            // Reflect velocity on the x-axis if we hit something on the x-axis
+            if reflect_x {
+                ball_velocity.x = -ball_velocity.x;
+            }
+
+            // Reflect velocity on the y-axis if we hit something on the y-axis
+            if reflect_y {
+                ball_velocity.y = -ball_velocity.y;
+            }
+
+            // A paddle or wall the ball stays in touch with for a few frames only hit it once
+            if reflect_x || reflect_y {
+                match player_query.get(collider_entity) {
+                    Ok(player) => {
+                        ball.hits += 1;
+                        events.send(SimulationEvent::PaddleHit { handle: player.handle });
+                    }
+                    Err(_) if maybe_brick.is_none() => events.send(SimulationEvent::WallHit),
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+}
+
+fn move_players(
+    mode: Res<GameMode>,
+    frame_rate: Res<FrameRate>,
+    inputs: Res<PlayerInputs>,
+    mut player_query: Query<(&mut Transform, &Player), With<Rollback>>,
+) {
+    for (mut transform, player) in player_query.iter_mut() {
+        let direction = direction(inputs[player.handle].0);
+
+        if direction == Vec2::ZERO {
+            continue;
+        }
+
+        let move_delta = (direction * PLAYER_MOVE_SPEED * frame_rate.time_step()).extend(0.0);
+
+        transform.translation += move_delta;
+
+        // Update the paddle position,
+        // making sure it doesn't leave the player's zone
+        // Widened paddles are kept as far from its edges
+        let zone = paddle_zone(*mode, player.handle);
+        let half_size = transform.scale.truncate() / 2.0 + PADDLE_PADDING;
+        let position = transform.translation.truncate().clamp(zone.min + half_size, zone.max - half_size);
+        transform.translation = position.extend(transform.translation.z);
+    }
+}
+
+/// Keeps balls going as fast as their hits since the serve make them, in the direction they're going.
+fn pace_balls(level: Res<Level>, mut query: Query<(&Ball, &mut Velocity)>) {
+    let speeds = LevelSpeed::of(&level);
+    for (ball, mut velocity) in &mut query {
+        velocity.0 = velocity.normalize() * speeds.after(ball.hits);
+    }
+}
+
+/// Serves balls that got into a goal again, from the middle, as slow as the level serves them.
+/// In versus the other player scores for it and the ball is served at the player who let it in,
+/// in co-op it costs the team a life.
+fn lose_balls(
+    mode: Res<GameMode>,
+    level: Res<Level>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut rng: ResMut<RollbackRng>,
+    mut events: ResMut<FrameEvents>,
+    mut ball_query: Query<(&mut Ball, &mut Velocity, &mut Transform)>,
+) {
+    for (mut ball, mut velocity, mut transform) in &mut ball_query {
+        let y = transform.translation.y;
+        let defender = if y <= BOTTOM_WALL {
+            0
+        } else if *mode == GameMode::Versus && y >= TOP_WALL {
+            1
+        } else {
+            continue;
+        };
+
+        let towards = match *mode {
+            GameMode::Versus => {
+                scoreboard.players[1 - defender] += MISSED_BALL_POINTS;
+                Some(defender)
+            }
+            GameMode::CoOp => {
+                scoreboard.lives = scoreboard.lives.saturating_sub(1);
+                None
+            }
+        };
+        events.send(SimulationEvent::BallLost { defender: towards });
+        *ball = Ball::default();
+        *velocity = serve(&mut rng, towards, LevelSpeed::of(&level).serve);
+        transform.translation = serve_position(*mode);
+    }
+}
+
+/// What's left of a wall: its bricks and the power-ups they dropped.
+#[derive(SystemParam)]
+struct WallRemains<'w, 's> {
+    bricks: Query<'w, 's, (), With<Brick>>,
+    power_ups: Query<'w, 's, Entity, With<PowerUp>>,
+}
+
+/// In co-op, a cleared wall makes way for the next level's, and the ball is served anew.
+/// Power-ups still falling are gone, their ids belong to the new wall's bricks.
+fn next_level(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    mut level: ResMut<Level>,
+    mut rng: ResMut<RollbackRng>,
+    mut events: ResMut<FrameEvents>,
+    remains: WallRemains,
+    mut ball_query: Query<(&mut Ball, &mut Velocity, &mut Transform)>,
+) {
+    if *mode != GameMode::CoOp || !remains.bricks.is_empty() {
+        return;
+    }
+
+    level.number += 1;
+    events.send(SimulationEvent::LevelCleared { level: level.number });
+    for power_up in &remains.power_ups {
+        commands.entity(power_up).despawn();
+    }
+    spawn_bricks(&mut commands, *mode, level.first_brick_id, &mut rng);
+    for (mut ball, mut velocity, mut transform) in &mut ball_query {
+        *ball = Ball::default();
+        *velocity = serve(&mut rng, None, LevelSpeed::of(&level).serve);
+        transform.translation = serve_position(*mode);
+    }
+}
+
+/// Widens the paddles catching power-ups, while those falling past the paddles are gone for good.
+/// Power-ups in versus fall past the paddle of the wall they came from, only the player they're for catches them.
+fn collect_power_ups(
+    mut commands: Commands,
+    frame_rate: Res<FrameRate>,
+    mut events: ResMut<FrameEvents>,
+    power_up_query: Query<(Entity, &Transform, &PowerUp)>,
+    paddle_query: Query<(Entity, &Transform, &Player), With<Paddle>>,
+) {
+    for (power_up, power_up_transform, power_up_for) in &power_up_query {
+        let caught_by = paddle_query.iter().find(|(_, paddle_transform, player)| {
+            if power_up_for.for_player.is_some_and(|handle| handle != player.handle) {
+                return false;
+            }
+            let collision = collide(
+                power_up_transform.translation,
+                power_up_transform.scale.truncate(),
+                paddle_transform.translation,
+                paddle_transform.scale.truncate(),
+            );
+            collision.is_some()
+        });
+
+        let y = power_up_transform.translation.y;
+        if let Some((paddle, _, player)) = caught_by {
+            events.send(SimulationEvent::PowerUpCollected { handle: player.handle });
+            commands.entity(paddle).insert(Widened { frames_left: frame_rate.frames(WIDENED_SECONDS) });
+            commands.entity(power_up).despawn();
+        } else if !(BOTTOM_WALL..=TOP_WALL).contains(&y) {
+            commands.entity(power_up).despawn();
+        }
+    }
+}
+
+/// Keeps paddles wide while their power-up lasts, and shrinks them back once it wears off.
+fn wear_off_power_ups(mut commands: Commands, mut query: Query<(Entity, &mut Transform, &mut Widened)>) {
+    for (entity, mut transform, mut widened) in &mut query {
+        transform.scale.x = PADDLE_SIZE.x * WIDENED_PADDLE_SCALE;
+        widened.frames_left = widened.frames_left.saturating_sub(1);
+        if widened.frames_left == 0 {
+            transform.scale.x = PADDLE_SIZE.x;
+            commands.entity(entity).remove::<Widened>();
+        }
+    }
+}
+
+/// Ends the round once a player's wall is broken in versus, or once the last life is lost in co-op.
+fn end_round(
+    mode: Res<GameMode>,
+    frame_count: Res<FrameCount>,
+    scoreboard: Res<Scoreboard>,
+    brick_query: Query<&Brick>,
+    mut round: ResMut<RoundOver>,
+) {
+    if round.frame.is_some() {
+        return;
+    }
+
+    let standing = |handle| brick_query.iter().any(|brick| brick.owner == Some(handle));
+    let (over, winner) = match *mode {
+        GameMode::Versus => match (standing(0), standing(1)) {
+            (true, true) => (false, None),
+            (true, false) => (true, Some(0)),
+            (false, true) => (true, Some(1)),
+            (false, false) => (true, None),
+        },
+        GameMode::CoOp => (scoreboard.lives == 0, None),
+    };
+    if over {
+        round.frame = Some(frame_count.frame);
+        round.winner = winner;
+    }
+}
+
+fn round_in_play(round: Res<RoundOver>) -> ShouldRun {
+    match round.frame {
+        None => ShouldRun::Yes,
+        Some(_) => ShouldRun::No,
+    }
+}
+
+/// Spawns everything the simulation needs, in a fixed order so that rollback ids match between peers.
+pub fn spawn_simulation(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    seed: Res<Seed>,
+    mode: Res<GameMode>,
+) {
+    spawn_arena(&mut commands, *mode);
+
+    let mut rng = RollbackRng::new(*seed);
+    spawn_rollback_entities(&mut commands, &mut rip, &mut rng, *mode);
+    commands.insert_resource(rng);
+}
+
+/// Spawns the walls around the arena. Balls pass through goals, they're what the paddles defend:
+/// the floor in co-op, and the ceiling too in versus.
+fn spawn_arena(commands: &mut Commands, mode: GameMode) {
+    commands.spawn(WallBundle::new(WallLocation::Left));
+    commands.spawn(WallBundle::new(WallLocation::Right));
+    commands.spawn((WallLocation::Bottom.transform(), Wall, Goal));
+    match mode {
+        GameMode::Versus => commands.spawn((WallLocation::Top.transform(), Wall, Goal)),
+        GameMode::CoOp => commands.spawn(WallBundle::new(WallLocation::Top)),
+    };
+}
+
+/// Puts up the walls of the current `GameMode`'s arena, for when the mode changed along with the rest of the world.
+/// Walls aren't rolled back, they stay the same throughout a match.
+pub fn fit_arena(world: &mut World) {
+    let walls: Vec<Entity> = world.query_filtered::<Entity, With<Wall>>().iter(world).collect();
+    for wall in walls {
+        world.despawn(wall);
+    }
+
+    let mode = *world.resource::<GameMode>();
+    let mut queue = CommandQueue::default();
+    spawn_arena(&mut Commands::new(&mut queue, world), mode);
+    queue.apply(world);
+}
+
+/// Starts the simulation over from its first frame, with a wall of bricks grown from the current `Seed`.
+/// Rollback ids are handed out from scratch again, so peers resetting together end up with matching worlds.
+/// The GGRS session has to be restarted along with it, its frames start over too.
+pub fn reset_simulation(world: &mut World) {
+    let entities: Vec<Entity> = world.query_filtered::<Entity, With<Rollback>>().iter(world).collect();
+    for entity in entities {
+        world.despawn(entity);
+    }
+    fit_arena(world);
+
+    let mode = *world.resource::<GameMode>();
+    let mut rip = RollbackIdProvider::default();
+    let mut rng = RollbackRng::new(*world.resource::<Seed>());
+    let mut queue = CommandQueue::default();
+    spawn_rollback_entities(&mut Commands::new(&mut queue, world), &mut rip, &mut rng, mode);
+    queue.apply(world);
+
+    world.insert_resource(rip);
+    world.insert_resource(rng);
+    world.insert_resource(FrameCount { frame: 0 });
+    world.insert_resource(Scoreboard::default());
+    world.insert_resource(RoundOver::default());
+    clear_events(world);
+}
+
+/// Spawns the entities saved and loaded on rollback: the paddles, the ball and the bricks, laid out for `mode`.
+/// Where the ball is served and which bricks make up the walls is up to `rng`.
+fn spawn_rollback_entities(
+    commands: &mut Commands,
+    rip: &mut RollbackIdProvider,
+    rng: &mut RollbackRng,
+    mode: GameMode,
+) {
+    // Players
+    for handle in 0..2 {
+        commands.spawn((
+            Player { handle },
+            Rollback::new(rip.next_id()),
+            Transform {
+                translation: start_position(mode, handle),
+                scale: PADDLE_SIZE,
+                ..default()
+            },
+            Paddle,
+            Collider,
+        ));
+    }
+
+    // Ball
+    // A versus round is served at either player
+    let towards = match mode {
+        GameMode::Versus => Some(rng.below(2) as PlayerHandle),
+        GameMode::CoOp => None,
+    };
+    commands.spawn((
+        Rollback::new(rip.next_id()),
+        Transform::from_translation(serve_position(mode)).with_scale(BALL_SIZE),
+        Ball::default(),
+        serve(rng, towards, LEVEL_SPEEDS[0].serve),
+    ));
+
+    // Bricks
+    // Every place in the walls gets an id, whether it holds a brick or not, for the walls of later levels to reuse
+    let (n_rows, n_columns, walls) = brick_walls(mode);
+    let first_brick_id = rip.next_id();
+    for _ in 1..walls.len() * n_rows * n_columns {
+        rip.next_id();
+    }
+    spawn_bricks(commands, mode, first_brick_id, rng);
+    commands.insert_resource(Level {
+        number: 1,
+        first_brick_id,
+    });
+}
+
+/// The ball's velocity as it's served at `speed` towards a player's goal, or downwards at the co-op paddles,
+/// with a random slope to either side.
+fn serve(rng: &mut RollbackRng, towards: Option<PlayerHandle>, speed: f32) -> Velocity {
+    let slope = rng.range(SERVE_MIN_SLOPE, SERVE_MAX_SLOPE);
+    let y = towards.map_or(-1.0, towards_goal);
+    let serve_direction = if rng.one_in(2) { Vec2::new(-slope, y) } else { Vec2::new(slope, y) };
+    Velocity(serve_direction.normalize() * speed)
+}
+
+/// Where the ball is served from: in versus the middle of the divider, so neither player is closer.
+fn serve_position(mode: GameMode) -> Vec3 {
+    match mode {
+        GameMode::Versus => Vec3::new((LEFT_WALL + RIGHT_WALL) / 2.0, DIVIDER_WALL, BALL_STARTING_POSITION.z),
+        GameMode::CoOp => BALL_STARTING_POSITION,
+    }
+}
+
+/// A wall of bricks, laid out row by row from `origin`, the center of its first brick.
+struct BrickWall {
+    owner: Option<PlayerHandle>,
+    origin: Vec2,
+    /// How far up each row is from the last, negative for walls growing down from the ceiling
+    row_step: f32,
+}
+
+/// The walls of bricks of a round, all of them the same number of rows and columns.
+/// Co-op has one wall under the ceiling, versus a wall in front of each player's goal, the second mirroring the first.
+fn brick_walls(mode: GameMode) -> (usize, usize, Vec<BrickWall>) {
+    let (n_rows, n_columns, offset) = brick_grid();
+    let row_step = BRICK_SIZE.y + GAP_BETWEEN_BRICKS;
+    match mode {
+        GameMode::Versus => {
+            let origin = Vec2::new(
+                offset.x,
+                BOTTOM_WALL + WALL_THICKNESS / 2.0 + GAP_BETWEEN_BRICKS_AND_GOAL + BRICK_SIZE.y / 2.0,
+            );
+            let walls = vec![
+                BrickWall { owner: Some(0), origin, row_step },
+                BrickWall {
+                    owner: Some(1),
+                    origin: mirrored(origin.extend(0.0)).truncate(),
+                    row_step: -row_step,
+                },
+            ];
+            (VERSUS_BRICK_ROWS, n_columns, walls)
+        }
+        GameMode::CoOp => (n_rows, n_columns, vec![BrickWall { owner: None, origin: offset, row_step }]),
+    }
+}
+
+/// How many rows and columns of bricks fit under the ceiling, and where the bottom left one goes.
+fn brick_grid() -> (usize, usize, Vec2) {
+    // Negative scales result in flipped sprites / meshes,
+    // which is definitely not what we want here
+    assert!(BRICK_SIZE.x > 0.0);
+    assert!(BRICK_SIZE.y > 0.0);
+
+    let paddle_y = BOTTOM_WALL + GAP_BETWEEN_PADDLE_AND_FLOOR;
+
+    let total_width_of_bricks = (RIGHT_WALL - LEFT_WALL) - 2. * GAP_BETWEEN_BRICKS_AND_SIDES;
+    let bottom_edge_of_bricks = paddle_y + GAP_BETWEEN_PADDLE_AND_BRICKS;
+    let total_height_of_bricks = TOP_WALL - bottom_edge_of_bricks - GAP_BETWEEN_BRICKS_AND_CEILING;
+
+    assert!(total_width_of_bricks > 0.0);
+    assert!(total_height_of_bricks > 0.0);
+
+    // Given the space available, compute how many rows and columns of bricks we can fit
+    let n_columns = (total_width_of_bricks / (BRICK_SIZE.x + GAP_BETWEEN_BRICKS)).floor() as usize;
+    let n_rows = (total_height_of_bricks / (BRICK_SIZE.y + GAP_BETWEEN_BRICKS)).floor() as usize;
+    let n_vertical_gaps = n_columns - 1;
+
+    // Because we need to round the number of columns,
+    // the space on the top and sides of the bricks only captures a lower bound, not an exact value
+    let center_of_bricks = (LEFT_WALL + RIGHT_WALL) / 2.0;
+    let left_edge_of_bricks = center_of_bricks
+        // Space taken up by the bricks
+        - (n_columns as f32 / 2.0 * BRICK_SIZE.x)
+        // Space taken up by the gaps
+        - n_vertical_gaps as f32 / 2.0 * GAP_BETWEEN_BRICKS;
+
+    // In Bevy, the `translation` of an entity describes the center point,
+    // not its bottom-left corner
+    let offset_x = left_edge_of_bricks + BRICK_SIZE.x / 2.;
+    let offset_y = bottom_edge_of_bricks + BRICK_SIZE.y / 2.;
+
+    (n_rows, n_columns, Vec2::new(offset_x, offset_y))
+}
+
+/// Spawns the walls of bricks of `mode`, with some left out by `rng`. Each place in the walls has its own rollback id,
+/// counting up from `first_id` wall by wall and row by row.
+/// Versus walls are alike, bricks left out and power-ups included, so neither player is favored.
+fn spawn_bricks(commands: &mut Commands, mode: GameMode, first_id: u32, rng: &mut RollbackRng) {
+    let (n_rows, n_columns, walls) = brick_walls(mode);
+
+    // Pick the bricks left out on the left half, the right half mirrors it
+    let missing: Vec<Vec<bool>> = (0..n_rows)
+        .map(|_| (0..n_columns.div_ceil(2)).map(|_| rng.one_in(MISSING_BRICK_ODDS)).collect())
+        .collect();
+    // Then which of the bricks left drop a power-up, None where there's no brick
+    let power_ups: Vec<Vec<Option<bool>>> = missing
+        .iter()
+        .map(|missing| {
+            (0..n_columns)
+                .map(|column| (!missing[column.min(n_columns - 1 - column)]).then(|| rng.one_in(POWER_UP_ODDS)))
+                .collect()
+        })
+        .collect();
+
+    for (index, wall) in walls.iter().enumerate() {
+        let first_wall_id = first_id + (index * n_rows * n_columns) as u32;
+        for (row, power_ups) in power_ups.iter().enumerate() {
+            for (column, power_up) in power_ups.iter().enumerate() {
+                let Some(power_up) = *power_up else {
+                    continue;
+                };
+
+                let brick_position = Vec2::new(
+                    wall.origin.x + column as f32 * (BRICK_SIZE.x + GAP_BETWEEN_BRICKS),
+                    wall.origin.y + row as f32 * wall.row_step,
+                );
+
+                // brick
+                commands.spawn((
+                    Rollback::new(first_wall_id + (row * n_columns + column) as u32),
+                    Transform {
+                        translation: brick_position.extend(0.0),
+                        scale: Vec3::new(BRICK_SIZE.x, BRICK_SIZE.y, 1.0),
+                        ..default()
+                    },
+                    Brick {
+                        power_up,
+                        owner: wall.owner,
+                    },
+                    Collider,
+                ));
+            }
+        }
+    }
+}
+
+/// The systems advancing the simulation by one frame, in order.
+pub fn rollback_schedule() -> Schedule {
+    Schedule::default().with_stage(
+        "ROLLBACK_STAGE",
+        SystemStage::single_threaded()
+            // Bricks broken last frame have been despawned by now
+            .with_system(end_round)
+            .with_system_set(
+                SystemSet::new()
+                    .with_run_criteria(round_in_play)
+                    .after(end_round)
+                    .with_system(next_level)
+                    .with_system(move_players.after(next_level))
+                    .with_system(apply_velocity.after(move_players))
+                    .with_system(check_for_collisions.after(apply_velocity))
+                    .with_system(lose_balls.after(check_for_collisions))
+                    .with_system(pace_balls.after(lose_balls))
+                    .with_system(collect_power_ups.after(pace_balls))
+                    .with_system(wear_off_power_ups.after(collect_power_ups)),
+            )
+            .with_system(log_frame_events.after(wear_off_power_ups))
+            .with_system(increase_frame_system.after(log_frame_events))
+    )
+}
+
+/// Builds the rollback simulation, with `input_system` providing each local player's input.
+pub fn build_simulation<Params>(
+    app: &mut App,
+    input_system: impl IntoSystem<PlayerHandle, u8, Params>,
+    pacing: Pacing,
+) {
+    RollbackPlugin::new()
+        .with_pacing(pacing)
+        .with_input_system(input_system)
+        .with_rollback_schedule(rollback_schedule())
+        .register_rollback_component::<Transform>()
+        .register_rollback_component::<Velocity>()
+        .register_rollback_component::<Player>()
+        .register_rollback_component::<Paddle>()
+        .register_rollback_component::<Ball>()
+        .register_rollback_component::<Brick>()
+        .register_rollback_component::<Collider>()
+        .register_rollback_component::<PowerUp>()
+        .register_rollback_component::<Widened>()
+        .register_rollback_resource::<Scoreboard>()
+        .register_rollback_resource::<FrameCount>()
+        .register_rollback_resource::<RoundOver>()
+        .register_rollback_resource::<RollbackRng>()
+        .register_rollback_resource::<GameMode>()
+        .register_rollback_resource::<Level>()
+        .build(app);
+
+    app.add_startup_system(spawn_simulation)
+        .init_resource::<Seed>()
+        .init_resource::<FrameRate>()
+        .init_resource::<GameMode>()
+        .init_resource::<Level>()
+        .insert_resource(FrameCount { frame: 0 })
+        .insert_resource(Scoreboard::default())
+        .insert_resource(RoundOver::default());
+    build_events(app);
+}
+
+/// Builds an app that runs nothing but the simulation, with no window, renderer or audio.
+/// Once a GGRS session is started, every `App::update` advances exactly one frame.
+pub fn build_headless_app<Params>(app: &mut App, input_system: impl IntoSystem<PlayerHandle, u8, Params>) {
+    app.add_plugins(MinimalPlugins);
+    build_simulation(app, input_system, Pacing::Unpaced);
+}
+
+/// Hashes the state of the simulation, equal states always give equal hashes. Every rollback component
+/// and resource is part of it, a rollback component left out here could diverge without any test noticing.
+/// Floats are hashed by their bits, so the tiniest divergence between two runs shows up.
+pub fn checksum(world: &mut World) -> u64 {
+    let mut entities: Vec<(u32, u64)> = world
+        .query::<(
+            &Rollback,
+            &Transform,
+            Option<&Velocity>,
+            Option<&Player>,
+            Option<&Paddle>,
+            Option<&Ball>,
+            Option<&Brick>,
+            Option<&Collider>,
+            Option<&PowerUp>,
+            Option<&Widened>,
+        )>()
+        .iter(world)
+        .map(|(rollback, transform, velocity, player, paddle, ball, brick, collider, power_up, widened)| {
+            let mut hasher = DefaultHasher::new();
+            transform.translation.to_array().map(f32::to_bits).hash(&mut hasher);
+            transform.rotation.to_array().map(f32::to_bits).hash(&mut hasher);
+            transform.scale.to_array().map(f32::to_bits).hash(&mut hasher);
+            velocity.map(|velocity| velocity.to_array().map(f32::to_bits)).hash(&mut hasher);
+            (player, paddle, ball, brick, collider, power_up, widened).hash(&mut hasher);
+            (rollback.id(), hasher.finish())
+        })
+        .collect();
+    entities.sort_unstable_by_key(|(id, _)| *id);
+
+    let mut hasher = DefaultHasher::new();
+    entities.hash(&mut hasher);
+    let scoreboard = world.resource::<Scoreboard>();
+    (scoreboard.score, scoreboard.players, scoreboard.lives).hash(&mut hasher);
+    world.resource::<GameMode>().hash(&mut hasher);
+    world.resource::<Level>().hash(&mut hasher);
+    let round_over = world.resource::<RoundOver>();
+    (round_over.frame, round_over.winner).hash(&mut hasher);
+    world.resource::<FrameCount>().hash(&mut hasher);
+    world.resource::<RollbackRng>().hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,85 @@
+use bevy::{prelude::*, transform::TransformSystem};
+use bevy_ggrs::Rollback;
+use crate::rollback::{FrameProgress, LastFrames, RollbackCorrection};
+
+
+// Smoothing
+// The simulation moves things in steps of a frame, while displays may refresh several times a frame. Moving entities are
+// drawn between where the last two frames had them, so they glide at whatever rate the display refreshes. Those are the
+// last two frames the rollback stage advanced, even when an update advances several.
+// A rollback corrects a misprediction all at once, which would make the remote paddle or the ball jump across the screen.
+// Instead, what's drawn keeps an offset from the simulation that takes it back to where it was shown, and fades away.
+// Only `GlobalTransform` is touched, which the simulation never reads, so nothing here can desync a match.
+
+// Time constant of the fade, most of a correction is gone after a few frames
+const SMOOTHING_TIME: f32 = 0.05;
+// Corrections longer than this are shown as they are, gliding all that way would look stranger than the jump
+const MAX_SMOOTHED_DISTANCE: f32 = 150.0;
+// Offsets shorter than this are done fading
+const MIN_OFFSET: f32 = 0.01;
+
+
+/// Where a moving entity is drawn, relative to where it's simulated.
+#[derive(Component, Default)]
+pub struct Smoothing {
+    /// How far from its simulated position the entity is drawn, while a correction fades
+    offset: Vec3,
+}
+
+/// Keeps corrected entities where they were drawn, for now. The frames they're drawn between were resimulated already.
+fn absorb_corrections(mut corrections: EventReader<RollbackCorrection>, mut query: Query<&mut Smoothing>) {
+    for correction in corrections.iter() {
+        let Ok(mut smoothing) = query.get_mut(correction.entity) else {
+            continue;
+        };
+
+        smoothing.offset -= correction.delta;
+        if smoothing.offset.length() > MAX_SMOOTHED_DISTANCE {
+            smoothing.offset = Vec3::ZERO;
+        }
+    }
+}
+
+fn fade_offsets(time: Res<Time>, mut query: Query<&mut Smoothing>) {
+    let decay = (-time.delta_seconds() / SMOOTHING_TIME).exp();
+    for mut smoothing in &mut query {
+        if smoothing.offset == Vec3::ZERO {
+            continue;
+        }
+
+        smoothing.offset *= decay;
+        if smoothing.offset.length() < MIN_OFFSET {
+            smoothing.offset = Vec3::ZERO;
+        }
+    }
+}
+
+/// Draws entities between their last two frames and at their offset, over the transform propagated from the simulation.
+/// Entities that haven't been through two frames yet are drawn where they're simulated.
+fn apply_smoothing(
+    progress: Res<FrameProgress>,
+    last_frames: Res<LastFrames>,
+    mut query: Query<(&Rollback, &Transform, &Smoothing, &mut GlobalTransform)>,
+) {
+    for (rollback, transform, smoothing, mut global_transform) in &mut query {
+        let previous = last_frames.previous.get(&rollback.id());
+        let (Some(previous), Some(current)) = (previous, last_frames.current.get(&rollback.id())) else {
+            continue;
+        };
+
+        *global_transform = GlobalTransform::from(Transform {
+            translation: previous.lerp(*current, progress.0) + smoothing.offset,
+            ..*transform
+        });
+    }
+}
+
+/// Adds smoothing to the app, for entities with a `Smoothing` component.
+pub fn build_smoothing(app: &mut App) {
+    app.add_system(absorb_corrections)
+        .add_system(fade_offsets.after(absorb_corrections))
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            apply_smoothing.after(TransformSystem::TransformPropagate),
+        );
+}
@@ -0,0 +1,416 @@
+use bevy::{
+    ecs::{entity::EntityMap, reflect::ReflectMapEntities},
+    prelude::*,
+    reflect::{FromType, TypeRegistry},
+    utils::HashMap,
+};
+use bevy_ggrs::Rollback;
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::num::Wrapping;
+
+
+// Snapshot
+// A fork of bevy_ggrs 0.11's `WorldSnapshot`, which is `pub(crate)` upstream and so unusable by the forked stage in
+// `rollback`. Its capture, checksum and restore follow upstream's.
+
+// Snapshots leave the game as a header naming the format, followed by the snapshot with every value tagged by the index
+// of its type in the schema. The schema lists the serializable types by name, so a build that rolls back different types
+// refuses snapshots of another rather than misreading them.
+const SNAPSHOT_MAGIC: [u8; 4] = *b"BFST";
+/// Bumped whenever the layout of serialized snapshots changes.
+pub const SNAPSHOT_VERSION: u16 = 1;
+// Anything larger is assumed corrupt, rather than allocated for
+const MAX_SNAPSHOT_SIZE: u64 = 1 << 20;
+
+/// Varints keep small numbers small, which most of a snapshot is.
+fn encoding() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_limit(MAX_SNAPSHOT_SIZE)
+}
+
+/// Why bytes couldn't be turned back into a snapshot, or a snapshot into bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The bytes don't start like a snapshot does.
+    NotASnapshot,
+    /// The snapshot is from a version of the format this build doesn't read.
+    UnsupportedVersion(u16),
+    /// The snapshot was taken by a build rolling back different types.
+    SchemaMismatch,
+    /// A type in the snapshot, or about to be put in one, has no `ReflectSnapshot`.
+    UnknownType(String),
+    /// The bytes broke off or don't decode.
+    Malformed(String),
+    /// The snapshot decoded, but not into the state it was taken from.
+    ChecksumMismatch,
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {version}, expected {SNAPSHOT_VERSION}"),
+            SnapshotError::SchemaMismatch => write!(f, "the snapshot was taken by a build with different rollback types"),
+            SnapshotError::UnknownType(type_name) => write!(f, "{type_name} can't be serialized"),
+            SnapshotError::Malformed(err) => write!(f, "malformed snapshot: {err}"),
+            SnapshotError::ChecksumMismatch => write!(f, "the snapshot doesn't match its checksum"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(err: bincode::Error) -> Self {
+        SnapshotError::Malformed(err.to_string())
+    }
+}
+
+/// Turns values of a registered type into bytes and back, so snapshots can leave the game, e.g. for another peer.
+#[derive(Clone)]
+pub struct ReflectSnapshot {
+    serialize: fn(&dyn Reflect) -> Option<Vec<u8>>,
+    deserialize: fn(&[u8]) -> Option<Box<dyn Reflect>>,
+}
+
+impl<T: FromReflect + Serialize + DeserializeOwned> FromType<T> for ReflectSnapshot {
+    fn from_type() -> Self {
+        ReflectSnapshot {
+            // Snapshots hold dynamic clones, which have to become the real type again first
+            serialize: |value| encoding().serialize(&T::from_reflect(value)?).ok(),
+            deserialize: |bytes| Some(Box::new(encoding().deserialize::<T>(bytes).ok()?)),
+        }
+    }
+}
+
+/// The names of the serializable types, in the order their indices refer to.
+struct Schema(Vec<String>);
+
+impl Schema {
+    fn new(type_registry: &TypeRegistry) -> Schema {
+        let mut type_names: Vec<String> = type_registry
+            .read()
+            .iter()
+            .filter(|registration| registration.data::<ReflectSnapshot>().is_some())
+            .map(|registration| registration.type_name().to_owned())
+            .collect();
+        type_names.sort();
+        Schema(type_names)
+    }
+
+    /// FNV-1a over the type names, which unlike `Hash` doesn't change from run to run.
+    /// The names come from `std::any::type_name`, which Rust doesn't promise to keep the same between compiler versions,
+    /// so builds by different compilers may refuse each other's snapshots even when their types match.
+    fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in self.0.iter().flat_map(|type_name| type_name.bytes().chain([0])) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+
+    fn index(&self, type_name: &str) -> Result<u16, SnapshotError> {
+        let index = self.0.binary_search_by(|name| name.as_str().cmp(type_name));
+        index
+            .map(|index| index as u16)
+            .map_err(|_| SnapshotError::UnknownType(type_name.to_owned()))
+    }
+
+    fn type_name(&self, index: u16) -> Result<&str, SnapshotError> {
+        self.0
+            .get(index as usize)
+            .map(String::as_str)
+            .ok_or_else(|| SnapshotError::Malformed(format!("no type with index {index}")))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotHeader {
+    magic: [u8; 4],
+    version: u16,
+    schema: u64,
+}
+
+/// A value as it travels, tagged with the index of its type in the schema.
+type SerializedValue = (u16, Vec<u8>);
+
+#[derive(Serialize, Deserialize)]
+struct SerializedEntity {
+    entity: u64,
+    rollback_id: u32,
+    components: Vec<SerializedValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedSnapshot {
+    entities: Vec<SerializedEntity>,
+    resources: Vec<SerializedValue>,
+    checksum: u64,
+}
+
+fn serialize_value(value: &dyn Reflect, schema: &Schema, type_registry: &TypeRegistry) -> Result<SerializedValue, SnapshotError> {
+    // Dynamic clones go by the name of the type they stand in for
+    let type_name = value.type_name();
+    let bytes = type_registry
+        .read()
+        .get_with_name(type_name)
+        .and_then(|registration| registration.data::<ReflectSnapshot>())
+        .and_then(|reflect_snapshot| (reflect_snapshot.serialize)(value))
+        .ok_or_else(|| SnapshotError::UnknownType(type_name.to_owned()))?;
+    Ok((schema.index(type_name)?, bytes))
+}
+
+fn deserialize_value((index, bytes): &SerializedValue, schema: &Schema, type_registry: &TypeRegistry) -> Result<Box<dyn Reflect>, SnapshotError> {
+    let type_name = schema.type_name(*index)?;
+    let reflect_snapshot = type_registry
+        .read()
+        .get_with_name(type_name)
+        .and_then(|registration| registration.data::<ReflectSnapshot>().cloned())
+        .ok_or_else(|| SnapshotError::UnknownType(type_name.to_owned()))?;
+    (reflect_snapshot.deserialize)(bytes).ok_or_else(|| SnapshotError::Malformed(format!("{type_name} doesn't decode")))
+}
+
+fn add_hash(checksum: u64, value: &dyn Reflect) -> u64 {
+    match value.reflect_hash() {
+        Some(hash) => (Wrapping(checksum) + Wrapping(hash)).0,
+        None => checksum,
+    }
+}
+
+
+/// Maps rollback ids to the entities currently carrying them.
+fn rollback_id_map(world: &mut World) -> HashMap<u32, Entity> {
+    let mut rid_map = HashMap::default();
+    let mut query = world.query::<(Entity, &Rollback)>();
+    for (entity, rollback) in query.iter(world) {
+        assert!(!rid_map.contains_key(&rollback.id()));
+        rid_map.insert(rollback.id(), entity);
+    }
+    rid_map
+}
+
+/// The registered components of one `Rollback` tagged entity.
+struct RollbackEntity {
+    entity: Entity,
+    rollback_id: u32,
+    components: Vec<Box<dyn Reflect>>,
+}
+
+/// Holds the registered components of every `Rollback` tagged entity, as well as the registered resources.
+/// The `checksum` sums the hashes of everything hashable, which keeps it independent of the order entities are stored in.
+#[derive(Default)]
+pub struct WorldSnapshot {
+    entities: Vec<RollbackEntity>,
+    resources: Vec<Box<dyn Reflect>>,
+    pub checksum: u64,
+}
+
+impl WorldSnapshot {
+    /// Captures the rollback state of the world.
+    pub fn from_world(world: &World, type_registry: &TypeRegistry) -> WorldSnapshot {
+        let mut snapshot = WorldSnapshot::default();
+        let type_registry = type_registry.read();
+
+        // Create a `RollbackEntity` for every entity tagged with rollback
+        for archetype in world.archetypes().iter() {
+            let entities_offset = snapshot.entities.len();
+            for entity in archetype.entities() {
+                let entity = entity.entity();
+                if let Some(rollback) = world.get::<Rollback>(entity) {
+                    snapshot.entities.push(RollbackEntity {
+                        entity,
+                        rollback_id: rollback.id(),
+                        components: Vec::new(),
+                    });
+                }
+            }
+
+            // Fill in the registered components of those entities
+            for component_id in archetype.components() {
+                let reflect_component = world
+                    .components()
+                    .get_info(component_id)
+                    .and_then(|info| type_registry.get(info.type_id()?))
+                    .and_then(|registration| registration.data::<ReflectComponent>());
+                let Some(reflect_component) = reflect_component else {
+                    continue;
+                };
+
+                let rollback_entities = archetype
+                    .entities()
+                    .iter()
+                    .filter(|entity| world.get::<Rollback>(entity.entity()).is_some());
+                for (i, entity) in rollback_entities.enumerate() {
+                    if let Some(component) = reflect_component.reflect(world, entity.entity()) {
+                        snapshot.checksum = add_hash(snapshot.checksum, component);
+                        snapshot.entities[entities_offset + i].components.push(component.clone_value());
+                    }
+                }
+            }
+        }
+
+        // Clone every registered resource
+        for (component_id, _) in world.storages().resources.iter() {
+            let reflect_resource = world
+                .components()
+                .get_info(component_id)
+                .and_then(|info| type_registry.get(info.type_id()?))
+                .and_then(|registration| registration.data::<ReflectResource>());
+            if let Some(resource) = reflect_resource.and_then(|reflect_resource| reflect_resource.reflect(world)) {
+                snapshot.checksum = add_hash(snapshot.checksum, resource);
+                snapshot.resources.push(resource.clone_value());
+            }
+        }
+
+        snapshot
+    }
+
+    /// Encodes the snapshot, every type in it needs to be registered with `ReflectSnapshot`.
+    pub fn to_bytes(&self, type_registry: &TypeRegistry) -> Result<Vec<u8>, SnapshotError> {
+        let schema = Schema::new(type_registry);
+        let serialize_values = |values: &[Box<dyn Reflect>]| {
+            values
+                .iter()
+                .map(|value| serialize_value(&**value, &schema, type_registry))
+                .collect::<Result<Vec<_>, SnapshotError>>()
+        };
+
+        let entities = self
+            .entities
+            .iter()
+            .map(|rollback_entity| {
+                Ok(SerializedEntity {
+                    entity: rollback_entity.entity.to_bits(),
+                    rollback_id: rollback_entity.rollback_id,
+                    components: serialize_values(&rollback_entity.components)?,
+                })
+            })
+            .collect::<Result<_, SnapshotError>>()?;
+        let serialized = SerializedSnapshot {
+            entities,
+            resources: serialize_values(&self.resources)?,
+            checksum: self.checksum,
+        };
+
+        let header = SnapshotHeader {
+            magic: SNAPSHOT_MAGIC,
+            version: SNAPSHOT_VERSION,
+            schema: schema.hash(),
+        };
+        let mut bytes = encoding().serialize(&header)?;
+        encoding().serialize_into(&mut bytes, &serialized)?;
+        Ok(bytes)
+    }
+
+    /// Decodes a snapshot encoded by `to_bytes`, by a build with the same types registered.
+    pub fn from_bytes(bytes: &[u8], type_registry: &TypeRegistry) -> Result<WorldSnapshot, SnapshotError> {
+        let mut reader = bytes;
+        let header: SnapshotHeader = encoding()
+            .deserialize_from(&mut reader)
+            .map_err(|_| SnapshotError::NotASnapshot)?;
+        if header.magic != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        if header.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(header.version));
+        }
+        let schema = Schema::new(type_registry);
+        if header.schema != schema.hash() {
+            return Err(SnapshotError::SchemaMismatch);
+        }
+
+        let serialized: SerializedSnapshot = encoding().deserialize(reader)?;
+        let deserialize_values = |values: &[SerializedValue]| {
+            values
+                .iter()
+                .map(|value| deserialize_value(value, &schema, type_registry))
+                .collect::<Result<Vec<_>, SnapshotError>>()
+        };
+
+        let mut snapshot = WorldSnapshot::default();
+        for entity in &serialized.entities {
+            snapshot.entities.push(RollbackEntity {
+                entity: Entity::from_bits(entity.entity),
+                rollback_id: entity.rollback_id,
+                components: deserialize_values(&entity.components)?,
+            });
+        }
+        snapshot.resources = deserialize_values(&serialized.resources)?;
+
+        // Summed the same way as when captured, the checksum tells whether everything came back as it was
+        let values = snapshot.entities.iter().flat_map(|entity| &entity.components).chain(&snapshot.resources);
+        snapshot.checksum = values.fold(0, |checksum, value| add_hash(checksum, &**value));
+        if snapshot.checksum != serialized.checksum {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+        Ok(snapshot)
+    }
+
+    /// Restores the world to the captured state, respawning entities that have been despawned since
+    /// and despawning those that didn't exist yet.
+    pub fn write_to_world(&self, world: &mut World, type_registry: &TypeRegistry) {
+        let type_registry = type_registry.read();
+        let mut rid_map = rollback_id_map(world);
+
+        // Maps the entities as they were when captured to the entities now holding their state
+        let mut entity_map = EntityMap::default();
+
+        for rollback_entity in self.entities.iter() {
+            let entity = *rid_map
+                .entry(rollback_entity.rollback_id)
+                .or_insert_with(|| world.spawn(Rollback::new(rollback_entity.rollback_id)).id());
+            entity_map.insert(rollback_entity.entity, entity);
+
+            for registration in type_registry.iter() {
+                let Some(reflect_component) = registration.data::<ReflectComponent>() else {
+                    continue;
+                };
+                let saved = rollback_entity
+                    .components
+                    .iter()
+                    .find(|component| component.type_name() == registration.type_name());
+
+                // Remove and re-insert rather than apply, applying merges lists instead of replacing them
+                if world.entity(entity).contains_type_id(registration.type_id()) {
+                    reflect_component.remove(world, entity);
+                }
+                if let Some(component) = saved {
+                    reflect_component.insert(world, entity, &**component);
+                }
+            }
+
+            // Whatever is left over in the map didn't exist yet and gets despawned
+            rid_map.remove(&rollback_entity.rollback_id);
+        }
+
+        for (_, entity) in rid_map.iter() {
+            world.despawn(*entity);
+        }
+
+        for registration in type_registry.iter() {
+            let Some(reflect_resource) = registration.data::<ReflectResource>() else {
+                continue;
+            };
+            let saved = self
+                .resources
+                .iter()
+                .find(|resource| resource.type_name() == registration.type_name());
+
+            match (reflect_resource.reflect(world).is_some(), saved) {
+                (true, Some(resource)) => reflect_resource.apply(world, &**resource),
+                (true, None) => reflect_resource.remove(world),
+                (false, Some(resource)) => reflect_resource.insert(world, &**resource),
+                (false, None) => {}
+            }
+        }
+
+        // Point entity references at the entities now holding their state
+        for registration in type_registry.iter() {
+            if let Some(map_entities) = registration.data::<ReflectMapEntities>() {
+                // Entities missing from the map were never rolled back, so leaving them as they are is correct
+                map_entities.map_entities(world, &entity_map).ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use serde::Serialize;
+use crate::chat::Chat;
+use crate::events::{confirm_events, ConfirmedEvent, SimulationEvent};
+use crate::game::player_color;
+use crate::history::unix_time;
+use crate::netcode::GgrsConfig;
+use crate::rollback::RollbackStats;
+use crate::series::{player_name, MatchOver};
+use crate::simulation::{GameMode, RoundOver};
+use crate::ui::*;
+
+
+// Stats
+// What each player did over a match, counted from confirmed events so that rollbacks can't skew them.
+// A match is a whole series in versus, and a game until the last life is lost in co-op.
+// Once it's over a summary shows under the result, and E exports the stats as a JSON file: written next to the game
+// natively, downloaded by the browser on the web.
+
+const EXPORT_KEY: KeyCode = KeyCode::E;
+
+const SUMMARY_FONT_SIZE: f32 = 24.0;
+const SUMMARY_PADDING: Val = Val::Px(15.0);
+// Keeps the summary clear of the result box in the middle of the screen
+const SUMMARY_BOTTOM_MARGIN: Val = Val::Px(40.0);
+const SUMMARY_LABEL_WIDTH: Val = Val::Px(180.0);
+const SUMMARY_PLAYER_WIDTH: Val = Val::Px(120.0);
+const SUMMARY_BACKGROUND_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const SUMMARY_NOTE_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+
+// The rows of the summary, a player's stats each
+const SUMMARY_ROWS: [&str; 4] = ["Bricks broken", "Paddle hits", "Balls lost", "Power-ups"];
+
+
+/// What a player did over the match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PlayerStats {
+    pub bricks_broken: u32,
+    /// Times the ball bounced off their paddle
+    pub paddle_hits: u32,
+    /// Balls that got into their goal, in versus
+    pub balls_lost: u32,
+    pub power_ups: u32,
+}
+
+impl PlayerStats {
+    /// The stats in the order of `SUMMARY_ROWS`.
+    fn rows(&self) -> [u32; 4] {
+        [self.bricks_broken, self.paddle_hits, self.balls_lost, self.power_ups]
+    }
+}
+
+/// The stats of the match being played, started over along with every match.
+#[derive(Resource, Clone, Debug, Serialize)]
+pub struct MatchStats {
+    pub mode: GameMode,
+    /// Rounds played so far, counting the one in play
+    pub rounds: u32,
+    /// Seconds the rounds were in play
+    pub duration: f32,
+    /// Each player's stats, by handle
+    pub players: [PlayerStats; 2],
+    /// Balls the team lost, in co-op
+    pub team_balls_lost: u32,
+    /// The most paddle hits between two serves
+    pub longest_rally: u32,
+    /// Rollbacks this game went through. The other game may have seen more or fewer, depending on whose inputs came late
+    pub rollbacks: u64,
+    /// Frames resimulated by a rollback, on average
+    pub average_rollback_frames: f32,
+    /// Paddle hits since the last serve
+    #[serde(skip)]
+    rally: u32,
+    /// Rollback stats as of the start of the match, as they count on from app to app
+    #[serde(skip)]
+    rollbacks_before: Option<RollbackStats>,
+}
+
+impl Default for MatchStats {
+    fn default() -> Self {
+        MatchStats {
+            mode: GameMode::default(),
+            rounds: 1,
+            duration: 0.0,
+            players: [PlayerStats::default(); 2],
+            team_balls_lost: 0,
+            longest_rally: 0,
+            rollbacks: 0,
+            average_rollback_frames: 0.0,
+            rally: 0,
+            rollbacks_before: None,
+        }
+    }
+}
+
+impl MatchStats {
+    /// Counts another round of the match, which starts with a serve.
+    pub fn next_round(&mut self) {
+        self.rounds += 1;
+        self.rally = 0;
+    }
+
+    /// Counts an event into the stats.
+    pub fn count(&mut self, event: &SimulationEvent) {
+        match *event {
+            SimulationEvent::BrickDestroyed { by_player: Some(handle), .. } => self.players[handle].bricks_broken += 1,
+            SimulationEvent::PaddleHit { handle } => {
+                self.players[handle].paddle_hits += 1;
+                self.rally += 1;
+                self.longest_rally = self.longest_rally.max(self.rally);
+            }
+            SimulationEvent::BallLost { defender } => {
+                match defender {
+                    Some(handle) => self.players[handle].balls_lost += 1,
+                    None => self.team_balls_lost += 1,
+                }
+                self.rally = 0;
+            }
+            // The next level starts with a serve
+            SimulationEvent::LevelCleared { .. } => self.rally = 0,
+            SimulationEvent::PowerUpCollected { handle } => self.players[handle].power_ups += 1,
+            SimulationEvent::BrickDestroyed { by_player: None, .. } | SimulationEvent::WallHit => {}
+        }
+    }
+
+    /// Takes the rollbacks of the match so far from the app's running `stats`.
+    pub fn count_rollbacks(&mut self, stats: RollbackStats) {
+        let before = *self.rollbacks_before.get_or_insert(stats);
+        self.rollbacks = stats.rollbacks - before.rollbacks;
+        let resimulated_frames = stats.resimulated_frames - before.resimulated_frames;
+        self.average_rollback_frames = match self.rollbacks {
+            0 => 0.0,
+            rollbacks => resimulated_frames as f32 / rollbacks as f32,
+        };
+    }
+
+    /// The stats as pretty-printed JSON.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// The summary shown once the match is over.
+#[derive(Component)]
+struct Summary;
+
+/// A column of the summary, with a player's stats.
+#[derive(Component)]
+struct SummaryColumn(usize);
+
+/// The line under the summary's columns, with the stats of the whole match.
+#[derive(Component)]
+struct SummaryNote;
+
+fn spawn_summary(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = ui_font(&asset_server);
+    let fixed_width = |width: Val, text: TextBundle| {
+        text.with_style(Style {
+            size: Size::new(width, Val::Auto),
+            ..default()
+        })
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::FlexEnd,
+                    align_items: AlignItems::Center,
+                    padding: UiRect::bottom(SUMMARY_BOTTOM_MARGIN),
+                    display: Display::None,
+                    ..default()
+                },
+                ..default()
+            },
+            Summary,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(SUMMARY_PADDING),
+                        ..default()
+                    },
+                    background_color: SUMMARY_BACKGROUND_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(row()).with_children(|parent| {
+                        let labels = format!("\n{}", SUMMARY_ROWS.join("\n"));
+                        parent.spawn(fixed_width(
+                            SUMMARY_LABEL_WIDTH,
+                            ui_text(labels, &font, SUMMARY_FONT_SIZE, UI_TEXT_COLOR),
+                        ));
+                        for handle in 0..2 {
+                            parent.spawn((
+                                fixed_width(
+                                    SUMMARY_PLAYER_WIDTH,
+                                    ui_text("", &font, SUMMARY_FONT_SIZE, player_color(handle)),
+                                )
+                                .with_text_alignment(TextAlignment::TOP_CENTER),
+                                SummaryColumn(handle),
+                            ));
+                        }
+                    });
+                    parent.spawn((
+                        ui_text("", &font, SUMMARY_FONT_SIZE, SUMMARY_NOTE_COLOR)
+                            .with_text_alignment(TextAlignment::CENTER),
+                        SummaryNote,
+                    ));
+                });
+        });
+}
+
+/// Counts the confirmed events of the match into its stats, until it's over.
+pub fn collect_stats(
+    mut events: EventReader<ConfirmedEvent>,
+    over: Option<Res<MatchOver>>,
+    mut stats: ResMut<MatchStats>,
+) {
+    if over.is_some() {
+        events.clear();
+        return;
+    }
+    for ConfirmedEvent { event, .. } in events.iter() {
+        stats.count(event);
+    }
+}
+
+/// Times the rounds while they're in play, and keeps up with the rollbacks they took.
+fn time_match(
+    time: Res<Time>,
+    session: Option<Res<Session<GgrsConfig>>>,
+    mode: Res<GameMode>,
+    round_over: Res<RoundOver>,
+    rollback_stats: Res<RollbackStats>,
+    mut stats: ResMut<MatchStats>,
+) {
+    if session.is_none() || round_over.frame.is_some() {
+        return;
+    }
+    stats.mode = *mode;
+    stats.duration += time.delta_seconds();
+    stats.count_rollbacks(*rollback_stats);
+}
+
+/// Shows the summary while the match is over.
+fn update_summary(
+    over: Option<Res<MatchOver>>,
+    stats: Res<MatchStats>,
+    mut summary_query: Query<&mut Style, With<Summary>>,
+    mut column_query: Query<(&mut Text, &SummaryColumn), Without<SummaryNote>>,
+    mut note_query: Query<&mut Text, With<SummaryNote>>,
+) {
+    let display = if over.is_some() { Display::Flex } else { Display::None };
+    for mut style in &mut summary_query {
+        style.display = display;
+    }
+    if over.is_none() {
+        return;
+    }
+
+    for (mut text, column) in &mut column_query {
+        let player = &stats.players[column.0];
+        let rows: Vec<String> = player.rows().iter().map(u32::to_string).collect();
+        text.sections[0].value = format!("{}\n{}", player_name(column.0), rows.join("\n"));
+    }
+
+    let seconds = stats.duration as u32;
+    let mut note = format!(
+        "{} rounds in {}:{:02}, the longest rally went {} hits",
+        stats.rounds,
+        seconds / 60,
+        seconds % 60,
+        stats.longest_rally
+    );
+    if stats.mode == GameMode::CoOp {
+        note += &format!("\nThe team lost {} balls", stats.team_balls_lost);
+    }
+    note += &format!(
+        "\n{} rollbacks, of {:.1} frames on average\nE to export the stats",
+        stats.rollbacks, stats.average_rollback_frames
+    );
+    for mut text in &mut note_query {
+        text.sections[0].value.clone_from(&note);
+    }
+}
+
+/// Exports the stats of a match that's over as JSON.
+fn export_stats(keys: Res<Input<KeyCode>>, chat: Option<Res<Chat>>, over: Option<Res<MatchOver>>, stats: Res<MatchStats>) {
+    if over.is_none() || !keys.just_pressed(EXPORT_KEY) || chat.is_some_and(|chat| chat.is_typing()) {
+        return;
+    }
+
+    let json = match stats.to_json() {
+        Ok(json) => json,
+        Err(err) => {
+            error!("Failed to export the stats: {}", err);
+            return;
+        }
+    };
+
+    let path = format!("bifrost-stats-{}.json", unix_time());
+    #[cfg(not(target_arch = "wasm32"))]
+    match std::fs::write(&path, json) {
+        Ok(()) => info!("Exported the stats to {}", path),
+        Err(err) => warn!("Failed to write {}: {}", path, err),
+    }
+    #[cfg(target_arch = "wasm32")]
+    match download(&path, &json) {
+        Some(()) => info!("Exported the stats to {}", path),
+        None => warn!("Failed to download {}, the stats are:\n{}", path, json),
+    }
+}
+
+/// Has the browser download `contents` as a file called `name`.
+#[cfg(target_arch = "wasm32")]
+fn download(name: &str, contents: &str) -> Option<()> {
+    use wasm_bindgen::JsCast;
+
+    let parts = js_sys::Array::of1(&contents.into());
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(
+        &parts,
+        web_sys::BlobPropertyBag::new().type_("application/json"),
+    )
+    .ok()?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).ok()?;
+
+    let link: web_sys::HtmlAnchorElement =
+        web_sys::window()?.document()?.create_element("a").ok()?.dyn_into().ok()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).ok()
+}
+
+/// Starts the stats over for a new match.
+pub fn reset_stats(world: &mut World) {
+    world.insert_resource(MatchStats::default());
+}
+
+/// Adds match stats to the app, with a summary once the match is over.
+pub fn build_stats(app: &mut App) {
+    app.init_resource::<MatchStats>()
+        .add_startup_system(spawn_summary)
+        .add_system(collect_stats.after(confirm_events))
+        .add_system(time_match)
+        .add_system(update_summary.after(collect_stats).after(time_match))
+        .add_system(export_stats);
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+
+
+// UI
+// The pieces every screen is built from: the menus, the lobby and the settings all look alike.
+
+pub const SCREEN_BACKGROUND_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.9);
+pub const UI_TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const BUTTON_COLOR: Color = Color::rgb(0.25, 0.25, 0.35);
+const BUTTON_HOVERED_COLOR: Color = Color::rgb(0.35, 0.35, 0.5);
+const BUTTON_PRESSED_COLOR: Color = Color::rgb(0.5, 0.5, 1.0);
+
+pub const TITLE_FONT_SIZE: f32 = 60.0;
+pub const UI_FONT_SIZE: f32 = 30.0;
+const UI_SPACING: Val = Val::Px(10.0);
+pub const BUTTON_SIZE: Size = Size {
+    width: Val::Px(220.0),
+    height: Val::Px(55.0),
+};
+/// For arrows and the like, next to what they change.
+pub const SMALL_BUTTON_SIZE: Size = Size {
+    width: Val::Px(55.0),
+    height: Val::Px(55.0),
+};
+
+
+pub fn ui_font(asset_server: &AssetServer) -> Handle<Font> {
+    asset_server.load("../assets/fonts/FiraSans-Bold.ttf")
+}
+
+pub fn ui_text(text: impl Into<String>, font: &Handle<Font>, font_size: f32, color: Color) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font: font.clone(),
+            font_size,
+            color,
+        },
+    )
+    .with_style(Style {
+        margin: UiRect::all(UI_SPACING),
+        ..default()
+    })
+}
+
+/// Spawns a button of `size`, labelled `label`, with `button` telling what it does when clicked.
+pub fn spawn_button(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, size: Size, button: impl Component) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    size,
+                    margin: UiRect::all(UI_SPACING),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: UI_FONT_SIZE,
+                    color: UI_TEXT_COLOR,
+                },
+            ));
+        });
+}
+
+pub fn row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+pub fn column() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+/// Covers the whole window, with its children stacked in the middle.
+pub fn screen() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: SCREEN_BACKGROUND_COLOR.into(),
+        ..default()
+    }
+}
+
+/// Lights buttons up as they're hovered and clicked.
+pub fn color_buttons(mut query: Query<(&Interaction, &mut BackgroundColor), Changed<Interaction>>) {
+    for (interaction, mut color) in &mut query {
+        *color = match interaction {
+            Interaction::Clicked => BUTTON_PRESSED_COLOR,
+            Interaction::Hovered => BUTTON_HOVERED_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+    }
+}
@@ -0,0 +1,90 @@
+//! Checks how fast the ball goes: faster with every few hits since the serve, never faster than its level allows,
+//! and as slow as the level serves it again once it's lost.
+
+use bevy::prelude::*;
+use bifrost::ai::{bot_input, Bots};
+use bifrost::components::{Ball, Velocity};
+use bifrost::netcode::{start_local_session, FrameCount};
+use bifrost::random::Seed;
+use bifrost::simulation::{
+    build_headless_app, GameMode, Level, BALL_STARTING_POSITION, BOTTOM_WALL, HITS_PER_SPEED_UP, LEVEL_SPEEDS,
+};
+
+/// A co-op match whose players stand still, a few frames in. Levels only change in co-op.
+fn co_op_app() -> App {
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.insert_resource(Seed(7))
+        .insert_resource(GameMode::CoOp)
+        .init_resource::<Bots>()
+        .add_startup_system(start_local_session);
+    while app.world.resource::<FrameCount>().frame < 2 {
+        app.update();
+    }
+    app
+}
+
+/// Puts the ball at `position` on `level`, having hit `hits` things since the serve, and plays a frame.
+/// Returns the ball's hits and speed after it.
+fn play_frame(app: &mut App, level: u32, hits: u32, position: Vec3) -> (u32, f32) {
+    app.world.resource_mut::<Level>().number = level;
+    let (mut ball, mut transform) = app.world.query::<(&mut Ball, &mut Transform)>().single_mut(&mut app.world);
+    ball.hits = hits;
+    transform.translation = position;
+
+    app.update();
+    let (ball, velocity) = app.world.query::<(&Ball, &Velocity)>().single(&app.world);
+    (ball.hits, velocity.length())
+}
+
+/// The ball's speed after a frame in the middle of the arena, clear of anything it could hit.
+fn speed_after(app: &mut App, level: u32, hits: u32) -> f32 {
+    let (hits_after, speed) = play_frame(app, level, hits, BALL_STARTING_POSITION);
+    assert_eq!(hits_after, hits, "The ball shouldn't have hit anything");
+    speed
+}
+
+fn assert_speed(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 0.01, "The ball goes {actual}, expected {expected}");
+}
+
+#[test]
+fn the_ball_speeds_up_every_few_hits() {
+    let mut app = co_op_app();
+    let speeds = &LEVEL_SPEEDS[0];
+
+    for hits in 0..HITS_PER_SPEED_UP {
+        assert_speed(speed_after(&mut app, 1, hits), speeds.serve);
+    }
+    assert_speed(speed_after(&mut app, 1, HITS_PER_SPEED_UP), speeds.serve + speeds.step);
+    assert_speed(speed_after(&mut app, 1, 3 * HITS_PER_SPEED_UP + 1), speeds.serve + 3.0 * speeds.step);
+}
+
+#[test]
+fn every_level_caps_the_speed() {
+    let mut app = co_op_app();
+
+    for (index, speeds) in LEVEL_SPEEDS.iter().enumerate() {
+        let level = index as u32 + 1;
+        assert_speed(speed_after(&mut app, level, 1000), speeds.max);
+        // Up to the cap, the ball speeds up by whole steps
+        let steps_below_cap = ((speeds.max - speeds.serve) / speeds.step).ceil() as u32 - 1;
+        let speed = speed_after(&mut app, level, steps_below_cap * HITS_PER_SPEED_UP);
+        assert_speed(speed, speeds.serve + steps_below_cap as f32 * speeds.step);
+    }
+
+    // Levels past the last play its speeds
+    let last = LEVEL_SPEEDS.last().unwrap();
+    assert_speed(speed_after(&mut app, LEVEL_SPEEDS.len() as u32 + 1, 1000), last.max);
+}
+
+#[test]
+fn a_serve_starts_the_speed_over() {
+    let mut app = co_op_app();
+
+    // The ball gets past the paddles on the second level, going as fast as it goes
+    let lost = Vec3::new(0.0, BOTTOM_WALL - 1.0, BALL_STARTING_POSITION.z);
+    let (hits, speed) = play_frame(&mut app, 2, 1000, lost);
+    assert_eq!(hits, 0, "A serve counts hits from scratch");
+    assert_speed(speed, LEVEL_SPEEDS[1].serve);
+}
@@ -0,0 +1,359 @@
+//! Plays the headless simulation with scripted inputs and checks that it always plays out the same way,
+//! with and without rollbacks. Any divergence here would desync real matches.
+
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs::{self, PlayerHandle}, Session};
+use bifrost::ai::{bot_input, Bot, Bots, Difficulty};
+use bifrost::components::{Ball, Brick, Paddle, PowerUp, Widened};
+use bifrost::netcode::{start_local_session, FrameCount, GgrsConfig};
+use bifrost::random::Seed;
+use bifrost::rollback::RollbackRegistry;
+use bifrost::simulation::{build_headless_app, checksum, reset_simulation, GameMode, Scoreboard, FRAME_RATE};
+use bifrost::snapshot::WorldSnapshot;
+
+// Ten seconds of play, long enough for the ball to break some bricks
+const FRAMES: usize = 10 * FRAME_RATE;
+const NUM_PLAYERS: usize = 2;
+
+/// Inputs of every player, by frame.
+#[derive(Resource, Clone)]
+struct InputScript(Vec<[u8; NUM_PLAYERS]>);
+
+impl InputScript {
+    fn idle() -> InputScript {
+        InputScript(vec![[0; NUM_PLAYERS]; FRAMES])
+    }
+
+    /// Random inputs that are held for a few frames at a time, like a person mashing keys would.
+    fn random(seed: u64) -> InputScript {
+        let mut rng = XorShift(seed);
+        let mut inputs = [0; NUM_PLAYERS];
+        let script = (0..FRAMES)
+            .map(|_| {
+                for input in inputs.iter_mut() {
+                    if rng.next() % 8 == 0 {
+                        *input = (rng.next() % 16) as u8;
+                    }
+                }
+                inputs
+            })
+            .collect();
+
+        InputScript(script)
+    }
+}
+
+/// A tiny deterministic generator, so scripts don't depend on anything outside this file.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn scripted_input(handle: In<PlayerHandle>, frame_count: Res<FrameCount>, script: Res<InputScript>) -> u8 {
+    script.0.get(frame_count.frame as usize).map_or(0, |inputs| inputs[handle.0])
+}
+
+/// How the players' inputs reach the simulation.
+#[derive(Clone, Copy)]
+enum Players {
+    Scripted,
+    Bots,
+}
+
+/// Which GGRS session drives the simulation.
+#[derive(Clone, Copy)]
+enum Sessions {
+    /// Both players local, frames are never rolled back.
+    Local,
+    /// Every frame rolls back and resimulates this many frames.
+    SyncTest(usize),
+}
+
+/// Plays a match and returns the state hash after every frame, along with the final score.
+fn play(players: Players, script: &InputScript, sessions: Sessions) -> (Vec<u64>, usize) {
+    play_seeded(players, script, sessions, Seed::default())
+}
+
+/// Plays a match whose serve, wall and power-ups grow from `seed`.
+fn play_seeded(players: Players, script: &InputScript, sessions: Sessions, seed: Seed) -> (Vec<u64>, usize) {
+    play_mode(players, script, sessions, seed, GameMode::Versus)
+}
+
+/// Plays a match of the given mode.
+fn play_mode(players: Players, script: &InputScript, sessions: Sessions, seed: Seed, mode: GameMode) -> (Vec<u64>, usize) {
+    let mut app = App::new();
+    match players {
+        Players::Scripted => build_headless_app(&mut app, scripted_input),
+        Players::Bots => build_headless_app(&mut app, bot_input),
+    }
+
+    app.insert_resource(seed).insert_resource(mode).insert_resource(script.clone()).insert_resource(Bots(
+        (0..NUM_PLAYERS).map(|handle| (handle, Bot::new(Difficulty::Hard))).collect(),
+    ));
+
+    match sessions {
+        Sessions::Local => app.add_startup_system(start_local_session),
+        Sessions::SyncTest(check_distance) => app.add_startup_system(move |mut commands: Commands| {
+            let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+                .with_num_players(NUM_PLAYERS)
+                .with_check_distance(check_distance)
+                .start_synctest_session()
+                .expect("Session failed to start.");
+            commands.insert_resource(Session::SyncTestSession(session));
+        }),
+    };
+
+    let hashes = (1..=FRAMES)
+        .map(|frame| {
+            app.update();
+            assert_eq!(app.world.resource::<FrameCount>().frame as usize, frame, "Every update should advance one frame");
+            checksum(&mut app.world)
+        })
+        .collect();
+
+    (hashes, app.world.resource::<Scoreboard>().score)
+}
+
+/// Reports the first frame two runs disagree on, which is far more useful than a wall of hashes.
+fn assert_same_hashes(expected: &[u64], actual: &[u64]) {
+    assert_eq!(expected.len(), actual.len());
+    if let Some(frame) = expected.iter().zip(actual).position(|(expected, actual)| expected != actual) {
+        panic!("Simulation diverged at frame {}", frame + 1);
+    }
+}
+
+#[test]
+fn same_inputs_play_out_the_same() {
+    let script = InputScript::random(0x5eed);
+    let (first, score) = play(Players::Scripted, &script, Sessions::Local);
+    let (second, _) = play(Players::Scripted, &script, Sessions::Local);
+
+    assert_same_hashes(&first, &second);
+    assert!(score > 0, "The ball should have broken some bricks, or brick rollbacks went untested");
+}
+
+#[test]
+fn different_inputs_play_out_differently() {
+    let (idle, _) = play(Players::Scripted, &InputScript::idle(), Sessions::Local);
+    let (random, _) = play(Players::Scripted, &InputScript::random(0x5eed), Sessions::Local);
+
+    assert_ne!(idle.last(), random.last(), "The state hash should capture the paddles' moves");
+}
+
+#[test]
+fn rollbacks_dont_change_the_outcome() {
+    for seed in [1, 0xdead_beef, 0x5eed] {
+        let script = InputScript::random(seed);
+        let (expected, _) = play(Players::Scripted, &script, Sessions::Local);
+
+        for check_distance in [2, 7] {
+            let (actual, _) = play(Players::Scripted, &script, Sessions::SyncTest(check_distance));
+            assert_same_hashes(&expected, &actual);
+        }
+    }
+}
+
+#[test]
+fn bots_play_out_the_same_with_rollbacks() {
+    // Versus walls sit behind the paddles, which bots this good rarely let the ball past. Co-op's wall is in plain reach
+    let script = InputScript::idle();
+    let (expected, score) = play_mode(Players::Bots, &script, Sessions::Local, Seed::default(), GameMode::CoOp);
+    let (actual, _) = play_mode(Players::Bots, &script, Sessions::SyncTest(4), Seed::default(), GameMode::CoOp);
+
+    assert_same_hashes(&expected, &actual);
+    assert!(score > 0, "The ball should have broken some bricks, or brick rollbacks went untested");
+}
+
+#[test]
+fn seeds_change_the_match_but_not_its_determinism() {
+    let script = InputScript::idle();
+    let (first, _) = play_seeded(Players::Bots, &script, Sessions::Local, Seed(1));
+    let (second, _) = play_seeded(Players::Bots, &script, Sessions::Local, Seed(2));
+    assert_ne!(first.first(), second.first(), "The serve and the wall should differ");
+
+    for seed in [Seed(1), Seed(2), Seed(0x5eed)] {
+        let (expected, _) = play_seeded(Players::Bots, &script, Sessions::Local, seed);
+        let (actual, _) = play_seeded(Players::Bots, &script, Sessions::SyncTest(7), seed);
+        assert_same_hashes(&expected, &actual);
+    }
+}
+
+#[test]
+fn co_op_plays_out_the_same_with_rollbacks() {
+    // Nobody moves, so balls get lost and lives run out
+    let script = InputScript::idle();
+    let (expected, _) = play_mode(Players::Scripted, &script, Sessions::Local, Seed(1), GameMode::CoOp);
+    let (actual, _) = play_mode(Players::Scripted, &script, Sessions::SyncTest(4), Seed(1), GameMode::CoOp);
+    assert_same_hashes(&expected, &actual);
+
+    let (versus, _) = play_mode(Players::Scripted, &script, Sessions::Local, Seed(1), GameMode::Versus);
+    assert_ne!(expected.first(), versus.first(), "The mode should be part of the checksum");
+}
+
+#[test]
+fn resetting_starts_the_same_match_over() {
+    let script = InputScript::random(0x5eed);
+    let (expected, _) = play(Players::Scripted, &script, Sessions::Local);
+
+    let mut app = App::new();
+    build_headless_app(&mut app, scripted_input);
+    app.insert_resource(script).add_system(start_local_session);
+
+    // Play half a match, then start over the way a rematch does
+    while app.world.resource::<FrameCount>().frame < FRAMES as u32 / 2 {
+        app.update();
+    }
+    app.world.remove_resource::<Session<GgrsConfig>>();
+    reset_simulation(&mut app.world);
+    // The stage notices the session is gone, and a new one starts
+    app.update();
+
+    let actual: Vec<u64> = (0..FRAMES)
+        .map(|_| {
+            app.update();
+            checksum(&mut app.world)
+        })
+        .collect();
+    assert_same_hashes(&expected, &actual);
+}
+
+#[test]
+fn a_serialized_match_plays_on_the_same() {
+    let script = InputScript::random(0x5eed);
+    let scripted_app = || {
+        let mut app = App::new();
+        build_headless_app(&mut app, scripted_input);
+        app.insert_resource(script.clone()).add_system(start_local_session);
+        app
+    };
+
+    // One player stays in the match, the other drops out and comes back to it, the way a reconnect does
+    let mut staying = scripted_app();
+    while staying.world.resource::<FrameCount>().frame < FRAMES as u32 / 2 {
+        staying.update();
+    }
+    let registry = staying.world.resource::<RollbackRegistry>().0.clone();
+    let bytes = WorldSnapshot::from_world(&staying.world, &registry)
+        .to_bytes(&registry)
+        .unwrap();
+
+    let mut returning = scripted_app();
+    returning.update();
+    returning.world.remove_resource::<Session<GgrsConfig>>();
+    WorldSnapshot::from_bytes(&bytes, &registry)
+        .unwrap()
+        .write_to_world(&mut returning.world, &registry);
+    assert_eq!(checksum(&mut staying.world), checksum(&mut returning.world));
+    // The stage notices the session is gone, and a new one starts
+    returning.update();
+
+    for _ in 0..FRAMES / 2 {
+        staying.update();
+        returning.update();
+        assert_eq!(checksum(&mut staying.world), checksum(&mut returning.world));
+    }
+}
+
+#[test]
+fn bots_start_over_with_the_match() {
+    let script = InputScript::idle();
+    let (expected, _) = play(Players::Bots, &script, Sessions::Local);
+
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.insert_resource(script)
+        .insert_resource(Bots((0..NUM_PLAYERS).map(|handle| (handle, Bot::new(Difficulty::Hard))).collect()))
+        .add_system(start_local_session);
+
+    // Bots decide again right after a reset, rather than once the frames catch up with their last decision
+    while app.world.resource::<FrameCount>().frame < FRAMES as u32 / 2 {
+        app.update();
+    }
+    app.world.remove_resource::<Session<GgrsConfig>>();
+    reset_simulation(&mut app.world);
+    app.update();
+
+    let actual: Vec<u64> = (0..FRAMES)
+        .map(|_| {
+            app.update();
+            checksum(&mut app.world)
+        })
+        .collect();
+    assert_same_hashes(&expected, &actual);
+}
+
+/// Changes the world with `change` and checks that the state hash tells.
+fn assert_checksum_notices(app: &mut App, what: &str, change: impl FnOnce(&mut World)) {
+    let before = checksum(&mut app.world);
+    change(&mut app.world);
+    assert_ne!(checksum(&mut app.world), before, "The state hash should capture {what}");
+}
+
+#[test]
+fn the_checksum_covers_every_rollback_component() {
+    let mut app = App::new();
+    build_headless_app(&mut app, scripted_input);
+    app.insert_resource(InputScript::idle()).add_startup_system(start_local_session);
+    app.update();
+
+    let ball = app.world.query_filtered::<Entity, With<Ball>>().single(&app.world);
+    let paddle = app.world.query_filtered::<Entity, With<Paddle>>().iter(&app.world).next().unwrap();
+    let brick = app.world.query_filtered::<Entity, With<Brick>>().iter(&app.world).next().unwrap();
+
+    // Speeding up follows the ball's hits, a match where they diverge goes on at different speeds
+    assert_checksum_notices(&mut app, "the ball's hits", |world| world.get_mut::<Ball>(ball).unwrap().hits += 1);
+    assert_checksum_notices(&mut app, "who hit the ball last", |world| {
+        world.get_mut::<Ball>(ball).unwrap().last_hit_by = Some(1);
+    });
+    assert_checksum_notices(&mut app, "the paddles' width", |world| {
+        world.get_mut::<Transform>(paddle).unwrap().scale.x *= 1.5;
+    });
+    assert_checksum_notices(&mut app, "widened paddles", |world| {
+        world.entity_mut(paddle).insert(Widened { frames_left: 10 });
+    });
+    assert_checksum_notices(&mut app, "how long a paddle stays wide", |world| {
+        world.get_mut::<Widened>(paddle).unwrap().frames_left -= 1;
+    });
+    assert_checksum_notices(&mut app, "bricks that drop power-ups", |world| {
+        let mut brick = world.get_mut::<Brick>(brick).unwrap();
+        brick.power_up = !brick.power_up;
+    });
+    assert_checksum_notices(&mut app, "falling power-ups", |world| {
+        let mut brick = world.entity_mut(brick);
+        brick.remove::<Brick>();
+        brick.insert(PowerUp { for_player: None });
+    });
+    assert_checksum_notices(&mut app, "who a power-up falls towards", |world| {
+        world.get_mut::<PowerUp>(brick).unwrap().for_player = Some(0);
+    });
+}
+
+#[test]
+fn diverging_hits_are_caught() {
+    let script = InputScript::random(0x5eed);
+    let (expected, _) = play(Players::Scripted, &script, Sessions::Local);
+
+    let mut app = App::new();
+    build_headless_app(&mut app, scripted_input);
+    app.insert_resource(script).add_startup_system(start_local_session);
+    let actual: Vec<u64> = (1..=FRAMES)
+        .map(|frame| {
+            app.update();
+            // One game counts a hit the other doesn't, halfway through
+            if frame == FRAMES / 2 {
+                let mut ball = app.world.query::<&mut Ball>().single_mut(&mut app.world);
+                ball.hits += 1;
+            }
+            checksum(&mut app.world)
+        })
+        .collect();
+
+    let diverged = expected.iter().zip(&actual).position(|(expected, actual)| expected != actual);
+    assert_eq!(diverged, Some(FRAMES / 2 - 1), "The hashes should part ways on the frame the hits did");
+}
@@ -0,0 +1,162 @@
+//! Checks that simulation events come out once their frames are confirmed, and as predicted events once their frames
+//! are first played out, exactly once however often rollbacks played those frames out.
+
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs, Session};
+use bifrost::ai::{bot_input, Bot, Bots, Difficulty};
+use bifrost::components::{Ball, Brick, Velocity};
+use bifrost::events::{confirm_events, ConfirmedEvent, PredictedEvent, SimulationEvent};
+use bifrost::netcode::{start_local_session, GgrsConfig};
+use bifrost::simulation::{
+    build_headless_app, GameMode, BALL_SIZE, BALL_SPEED, BOTTOM_WALL, BRICK_SIZE, FRAME_RATE, LEFT_WALL,
+    P1_START_POSITION,
+};
+
+const FRAMES: u32 = 10 * FRAME_RATE as u32;
+const NUM_PLAYERS: usize = 2;
+
+/// Events along with the frames they happened on.
+type Events = Vec<(u32, SimulationEvent)>;
+
+/// Every confirmed event so far, in the order they were sent.
+#[derive(Resource, Default)]
+struct Received(Events);
+
+/// Every predicted event so far, in the order they were sent.
+#[derive(Resource, Default)]
+struct Predicted(Events);
+
+fn receive(mut events: EventReader<ConfirmedEvent>, mut received: ResMut<Received>) {
+    received.0.extend(events.iter().map(|event| (event.frame, event.event.clone())));
+}
+
+fn receive_predicted(mut events: EventReader<PredictedEvent>, mut predicted: ResMut<Predicted>) {
+    predicted.0.extend(events.iter().map(|event| (event.frame, event.event.clone())));
+}
+
+/// A match of `mode` that receives its events, in a local session or a synctest checking `check_distance` frames.
+fn match_app(mode: GameMode, bots: Bots, check_distance: Option<usize>) -> App {
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.insert_resource(mode)
+        .insert_resource(bots)
+        .init_resource::<Received>()
+        .init_resource::<Predicted>()
+        .add_system(receive.after(confirm_events))
+        .add_system(receive_predicted.after(confirm_events));
+
+    match check_distance {
+        None => app.add_startup_system(start_local_session),
+        Some(check_distance) => app.add_startup_system(move |mut commands: Commands| {
+            let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+                .with_num_players(NUM_PLAYERS)
+                .with_check_distance(check_distance)
+                .start_synctest_session()
+                .expect("Session failed to start.");
+            commands.insert_resource(Session::SyncTestSession(session));
+        }),
+    };
+    app
+}
+
+/// Plays a co-op match between bots, whose wall is in reach, and returns the events confirmed along the way.
+fn play(check_distance: Option<usize>) -> Events {
+    let bots = Bots((0..NUM_PLAYERS).map(|handle| (handle, Bot::new(Difficulty::Hard))).collect());
+    let mut app = match_app(GameMode::CoOp, bots, check_distance);
+    for _ in 0..FRAMES {
+        app.update();
+    }
+    app.world.remove_resource::<Received>().unwrap().0
+}
+
+/// Plays the first second of a match of `mode` whose players stand still, once `setup` arranged the arena before
+/// its first frame. Returns the events confirmed and predicted along the way.
+fn play_out<Params>(
+    mode: GameMode,
+    setup: impl IntoSystemDescriptor<Params>,
+    check_distance: Option<usize>,
+) -> (Events, Events) {
+    let mut app = match_app(mode, Bots::default(), check_distance);
+    app.add_startup_system_to_stage(StartupStage::PostStartup, setup);
+    for _ in 0..FRAME_RATE {
+        app.update();
+    }
+    let received = app.world.remove_resource::<Received>().unwrap().0;
+    let predicted = app.world.remove_resource::<Predicted>().unwrap().0;
+    (received, predicted)
+}
+
+/// Checks that `setup` leads to `expected` exactly once, confirmed and predicted, whether or not rollbacks play its
+/// frame out again.
+fn assert_happens_once<Params>(
+    mode: GameMode,
+    setup: impl IntoSystemDescriptor<Params> + Clone,
+    expected: SimulationEvent,
+) {
+    let (local, _) = play_out(mode, setup.clone(), None);
+    let (confirmed, predicted) = play_out(mode, setup, Some(4));
+
+    for events in [&local, &confirmed, &predicted] {
+        let times = events.iter().filter(|(_, event)| *event == expected).count();
+        assert_eq!(times, 1, "{expected:?} should happen once in {events:?}");
+    }
+    let frame = |events: &[(u32, SimulationEvent)]| events.iter().find(|(_, event)| *event == expected).unwrap().0;
+    assert_eq!(frame(&local), frame(&confirmed));
+    assert_eq!(frame(&local), frame(&predicted));
+}
+
+/// Sends the ball from `position` at `direction`.
+fn aim_ball(position: Vec2, direction: Vec2) -> impl Fn(Query<(&mut Transform, &mut Velocity), With<Ball>>) + Clone {
+    move |mut query| {
+        let (mut transform, mut velocity) = query.single_mut();
+        transform.translation = position.extend(transform.translation.z);
+        velocity.0 = direction.normalize() * BALL_SPEED;
+    }
+}
+
+#[test]
+fn rollbacks_dont_repeat_or_lose_events() {
+    let expected = play(None);
+    let actual = play(Some(4));
+
+    assert!(
+        expected.iter().any(|(_, event)| matches!(event, SimulationEvent::BrickDestroyed { .. })),
+        "The ball should have broken some bricks"
+    );
+    // A synctest confirms its frames later, it may not have caught up with the last few
+    let confirmed = actual.last().map_or(0, |(frame, _)| *frame);
+    let expected: Vec<_> = expected.into_iter().filter(|(frame, _)| *frame <= confirmed).collect();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn bouncing_off_a_wall() {
+    // Below the wall and above the paddles, nearly level so it doesn't reach either once it bounces back
+    let setup = aim_ball(Vec2::new(LEFT_WALL + BALL_SIZE.x, -150.0), Vec2::new(-1.0, 0.1));
+    assert_happens_once(GameMode::CoOp, setup, SimulationEvent::WallHit);
+}
+
+#[test]
+fn getting_past_a_paddle() {
+    // Between the first player's wall and their goal, well to the side of their paddle
+    let setup = aim_ball(Vec2::new(LEFT_WALL + 2.0 * BALL_SIZE.x, BOTTOM_WALL + 1.0), Vec2::new(0.0, -1.0));
+    assert_happens_once(GameMode::Versus, setup, SimulationEvent::BallLost { defender: Some(0) });
+}
+
+#[test]
+fn catching_a_power_up() {
+    // A brick with a power-up just above the first player's paddle, and the ball coming down on it
+    let setup = |mut bricks: Query<(&mut Brick, &mut Transform), Without<Ball>>,
+                 mut ball: Query<(&mut Transform, &mut Velocity), With<Ball>>| {
+        let paddle = P1_START_POSITION;
+        let (mut brick, mut brick_transform) = bricks.iter_mut().next().unwrap();
+        brick.power_up = true;
+        brick_transform.translation = paddle + Vec3::new(0.0, 60.0, 0.0);
+
+        let (mut ball_transform, mut velocity) = ball.single_mut();
+        let above_brick = brick_transform.translation.y + (BRICK_SIZE.y + BALL_SIZE.y) / 2.0 + 5.0;
+        ball_transform.translation = Vec3::new(paddle.x, above_brick, ball_transform.translation.z);
+        velocity.0 = Vec2::new(0.0, -BALL_SPEED);
+    };
+    assert_happens_once(GameMode::CoOp, setup, SimulationEvent::PowerUpCollected { handle: 0 });
+}
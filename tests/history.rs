@@ -0,0 +1,99 @@
+//! Checks that the match history keeps the latest matches, that the leaderboard ranks them by score with the latest
+//! first among equals, and that a series adds up the points the records are made of.
+
+use bifrost::history::{MatchHistory, MatchRecord, MAX_RECORDS};
+use bifrost::series::Series;
+use bifrost::simulation::GameMode;
+
+/// A versus match `winner` won with `points`, finished at `finished_at`.
+fn versus(winner: usize, points: [usize; 2], finished_at: u64) -> MatchRecord {
+    let mut wins = [0; 2];
+    wins[winner] = 2;
+    MatchRecord {
+        mode: GameMode::Versus,
+        players: ["Left".to_owned(), "Right".to_owned()],
+        winner: Some(winner),
+        wins,
+        points,
+        team_score: None,
+        level: 1,
+        duration: 60.0,
+        finished_at,
+    }
+}
+
+/// A co-op game that ended with the team's `score`, finished at `finished_at`.
+fn co_op(score: usize, finished_at: u64) -> MatchRecord {
+    MatchRecord {
+        mode: GameMode::CoOp,
+        winner: None,
+        wins: [0; 2],
+        team_score: Some(score),
+        ..versus(0, [0; 2], finished_at)
+    }
+}
+
+#[test]
+fn the_oldest_matches_are_forgotten() {
+    let mut history = MatchHistory::default();
+    for finished_at in 0..MAX_RECORDS as u64 + 5 {
+        history.add(co_op(10, finished_at));
+    }
+
+    assert_eq!(history.records.len(), MAX_RECORDS);
+    assert_eq!(history.records.first().unwrap().finished_at, 5);
+    assert_eq!(history.records.last().unwrap().finished_at, MAX_RECORDS as u64 + 4);
+}
+
+#[test]
+fn the_leaderboard_ranks_by_score() {
+    let mut history = MatchHistory::default();
+    history.add(co_op(30, 1));
+    // Versus matches rank by the winner's points
+    history.add(versus(1, [50, 20], 2));
+    history.add(versus(0, [40, 90], 3));
+    history.add(co_op(70, 4));
+
+    let ranked: Vec<u64> = history.leaderboard().iter().map(|record| record.finished_at).collect();
+    assert_eq!(ranked, [4, 3, 1, 2]);
+}
+
+#[test]
+fn equal_scores_rank_the_latest_first() {
+    let mut history = MatchHistory::default();
+    history.add(co_op(20, 1));
+    history.add(co_op(50, 2));
+    history.add(co_op(20, 3));
+    history.add(versus(0, [20, 0], 4));
+    history.add(co_op(50, 5));
+
+    let ranked: Vec<u64> = history.leaderboard().iter().map(|record| record.finished_at).collect();
+    assert_eq!(ranked, [5, 2, 4, 3, 1]);
+}
+
+#[test]
+fn a_series_adds_up_its_points() {
+    let mut series = Series::new(3);
+    series.tally(Some(0), [40, 10]);
+    series.next_round(false);
+    series.tally(Some(1), [20, 60]);
+    assert_eq!(series.points, [60, 70]);
+    assert_eq!(series.wins, [1, 1]);
+
+    // A round counts once, even if a resumed match tallies it again
+    series.tally(Some(1), [20, 60]);
+    assert_eq!(series.points, [60, 70]);
+    assert_eq!(series.wins, [1, 1]);
+
+    series.next_round(false);
+    series.tally(None, [5, 5]);
+    assert_eq!(series.points, [65, 75]);
+    assert_eq!(series.wins, [1, 1], "A draw is nobody's win");
+
+    // A new match starts from scratch
+    series.next_round(true);
+    assert_eq!(series.points, [0, 0]);
+    assert_eq!(series.wins, [0, 0]);
+    series.tally(Some(0), [30, 0]);
+    assert_eq!(series.points, [30, 0]);
+}
@@ -0,0 +1,33 @@
+//! Checks the input delay picked for a round trip time.
+
+use bifrost::latency::InputDelaySettings;
+use bifrost::simulation::FrameRate;
+
+#[test]
+fn delay_covers_one_way_latency() {
+    let settings = InputDelaySettings { min: 0, max: 10, renegotiate: false };
+
+    // At 60 fps a frame lasts about 17 ms, and inputs take half the round trip to arrive
+    let frame_rate = FrameRate(60);
+    assert_eq!(settings.delay_for_rtt(0, frame_rate), 0);
+    assert_eq!(settings.delay_for_rtt(33, frame_rate), 1);
+    assert_eq!(settings.delay_for_rtt(100, frame_rate), 3);
+    assert_eq!(settings.delay_for_rtt(200, frame_rate), 6);
+}
+
+#[test]
+fn delay_follows_the_frame_rate() {
+    let settings = InputDelaySettings { min: 0, max: 20, renegotiate: false };
+
+    // Twice the frames cover the same latency at twice the frame rate
+    assert_eq!(settings.delay_for_rtt(100, FrameRate(120)), 6);
+    assert_eq!(settings.delay_for_rtt(200, FrameRate(30)), 3);
+}
+
+#[test]
+fn delay_stays_within_bounds() {
+    let settings = InputDelaySettings { min: 1, max: 4, renegotiate: false };
+
+    assert_eq!(settings.delay_for_rtt(0, FrameRate::default()), 1);
+    assert_eq!(settings.delay_for_rtt(1000, FrameRate::default()), 4);
+}
@@ -0,0 +1,97 @@
+//! Checks that loading a saved state starts the match over from it cleanly, with none of the events the abandoned
+//! match had yet to confirm or announce going off in the loaded one.
+
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs, Session};
+use bifrost::ai::{bot_input, Bot, Bots, Difficulty};
+use bifrost::events::{confirm_events, ConfirmedEvent, PredictedEvent, SimulationEvent};
+use bifrost::netcode::{start_local_session, FrameCount, GgrsConfig};
+use bifrost::savestate::{capture_state, restore_state};
+use bifrost::simulation::{build_headless_app, GameMode};
+
+// A synctest confirms its frames this far behind, leaving their events pending for a while
+const CHECK_DISTANCE: usize = 7;
+
+#[derive(Resource, Default)]
+struct Received {
+    /// Every confirmed and predicted event so far, in the order they were sent
+    events: Vec<(u32, SimulationEvent)>,
+    /// Events predicted but not confirmed yet
+    pending: usize,
+}
+
+fn receive(
+    mut confirmed: EventReader<ConfirmedEvent>,
+    mut predicted: EventReader<PredictedEvent>,
+    mut received: ResMut<Received>,
+) {
+    for PredictedEvent { frame, event } in predicted.iter() {
+        received.events.push((*frame, event.clone()));
+        received.pending += 1;
+    }
+    for ConfirmedEvent { frame, event } in confirmed.iter() {
+        received.events.push((*frame, event.clone()));
+        received.pending -= 1;
+    }
+}
+
+/// A co-op match between bots in a synctest, which goes on in a local session once a state is loaded.
+fn app() -> App {
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.insert_resource(GameMode::CoOp)
+        .insert_resource(Bots((0..2).map(|handle| (handle, Bot::new(Difficulty::Hard))).collect()))
+        .init_resource::<Received>()
+        .add_system(receive.after(confirm_events))
+        .add_startup_system(|mut commands: Commands| {
+            let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+                .with_num_players(2)
+                .with_check_distance(CHECK_DISTANCE)
+                .start_synctest_session()
+                .expect("Session failed to start.");
+            commands.insert_resource(Session::SyncTestSession(session));
+        })
+        .add_system(start_local_session);
+    app
+}
+
+fn play_to(app: &mut App, frame: u32) {
+    while app.world.resource::<FrameCount>().frame < frame {
+        app.update();
+    }
+}
+
+/// Loads `bytes` into `app` and plays on to `frame`, returning the events sent along the way by frame.
+/// Bricks broken on the same frame may come out in any order, depending on where the app keeps their entities.
+fn load_and_play(app: &mut App, bytes: &[u8], frame: u32) -> Vec<(u32, SimulationEvent)> {
+    restore_state(&mut app.world, bytes).unwrap();
+    app.world.resource_mut::<Received>().events.clear();
+    play_to(app, frame);
+    let mut events = std::mem::take(&mut app.world.resource_mut::<Received>().events);
+    events.sort_by_cached_key(|(frame, event)| (*frame, format!("{event:?}")));
+    events
+}
+
+#[test]
+fn loading_a_state_forgets_the_events_of_the_abandoned_match() {
+    let mut saved = app();
+    play_to(&mut saved, 600);
+    let bytes = capture_state(&saved.world).unwrap();
+
+    // A match that loads the state right away has nothing pending, it's what the loaded match looks like
+    let mut fresh = app();
+    play_to(&mut fresh, 1);
+    assert_eq!(fresh.world.resource::<Received>().pending, 0);
+    let expected = load_and_play(&mut fresh, &bytes, 700);
+
+    // One that loads it while events of earlier frames are yet to be confirmed has to forget them
+    let mut busy = app();
+    while busy.world.resource::<Received>().pending == 0 {
+        busy.update();
+    }
+    assert!(busy.world.resource::<FrameCount>().frame < 600, "The bots should have hit something by now");
+    let actual = load_and_play(&mut busy, &bytes, 700);
+
+    assert!(actual.iter().all(|(frame, _)| *frame >= 600), "Only frames of the loaded match have events");
+    assert_eq!(actual, expected);
+}
@@ -0,0 +1,78 @@
+//! Serializes the rollback world of the headless simulation, and checks that bytes which aren't a snapshot
+//! of this build are refused rather than misread.
+
+use bevy::{prelude::*, reflect::FromType};
+use bifrost::ai::{bot_input, Bot, Bots, Difficulty};
+use bifrost::netcode::{start_local_session, FrameCount};
+use bifrost::rollback::RollbackRegistry;
+use bifrost::simulation::{build_headless_app, checksum};
+use bifrost::snapshot::{ReflectSnapshot, SnapshotError, WorldSnapshot, SNAPSHOT_VERSION};
+
+/// An app a few seconds into a match between bots, where bricks are broken and the score isn't even.
+fn app_mid_match() -> App {
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.insert_resource(Bots((0..2).map(|handle| (handle, Bot::new(Difficulty::Hard))).collect()))
+        .add_system(start_local_session);
+    while app.world.resource::<FrameCount>().frame < 600 {
+        app.update();
+    }
+    app
+}
+
+fn serialize(app: &App) -> (Vec<u8>, RollbackRegistry) {
+    let registry = app.world.resource::<RollbackRegistry>().clone();
+    let bytes = WorldSnapshot::from_world(&app.world, &registry.0).to_bytes(&registry.0).unwrap();
+    (bytes, registry)
+}
+
+#[test]
+fn snapshots_restore_the_world_they_were_taken_of() {
+    let mut app = app_mid_match();
+    let (bytes, registry) = serialize(&app);
+    let expected = checksum(&mut app.world);
+
+    // Play on, then go back
+    for _ in 0..100 {
+        app.update();
+    }
+    assert_ne!(checksum(&mut app.world), expected);
+    WorldSnapshot::from_bytes(&bytes, &registry.0)
+        .unwrap()
+        .write_to_world(&mut app.world, &registry.0);
+    assert_eq!(checksum(&mut app.world), expected);
+}
+
+#[test]
+fn foreign_bytes_are_refused() {
+    let app = app_mid_match();
+    let (bytes, registry) = serialize(&app);
+    let decode = |bytes: &[u8]| WorldSnapshot::from_bytes(bytes, &registry.0).map(|_| ());
+
+    assert_eq!(decode(&[]), Err(SnapshotError::NotASnapshot));
+    assert_eq!(decode(b"not a snapshot at all"), Err(SnapshotError::NotASnapshot));
+
+    // The version follows the four bytes of magic
+    let mut newer = bytes.clone();
+    newer[4] = SNAPSHOT_VERSION as u8 + 1;
+    assert_eq!(decode(&newer), Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1)));
+
+    assert!(matches!(decode(&bytes[..bytes.len() / 2]), Err(SnapshotError::Malformed(_))));
+    assert!(decode(&bytes).is_ok());
+}
+
+#[test]
+fn a_build_with_other_rollback_types_refuses_the_snapshot() {
+    let app = app_mid_match();
+    let (bytes, _) = serialize(&app);
+
+    // Rolling back names too, say
+    let registry = app.world.resource::<RollbackRegistry>().0.clone();
+    registry.write().register::<Name>();
+    registry
+        .write()
+        .get_mut(std::any::TypeId::of::<Name>())
+        .unwrap()
+        .insert(<ReflectSnapshot as FromType<Name>>::from_type());
+    assert!(matches!(WorldSnapshot::from_bytes(&bytes, &registry), Err(SnapshotError::SchemaMismatch)));
+}
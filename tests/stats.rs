@@ -0,0 +1,78 @@
+//! Checks that match stats count what each player did, the longest rally between serves, and the rollbacks of the
+//! match rather than of the whole app.
+
+use bevy::prelude::*;
+use bifrost::events::SimulationEvent;
+use bifrost::rollback::RollbackStats;
+use bifrost::stats::{MatchStats, PlayerStats};
+
+fn count_all(stats: &mut MatchStats, events: &[SimulationEvent]) {
+    for event in events {
+        stats.count(event);
+    }
+}
+
+fn paddle_hits(handle: usize, hits: usize) -> Vec<SimulationEvent> {
+    vec![SimulationEvent::PaddleHit { handle }; hits]
+}
+
+#[test]
+fn events_count_for_their_players() {
+    let mut stats = MatchStats::default();
+    count_all(
+        &mut stats,
+        &[
+            SimulationEvent::BrickDestroyed { id: 1, by_player: Some(1), owner: Some(0), position: Vec2::ZERO },
+            // Bricks nobody broke aren't anybody's
+            SimulationEvent::BrickDestroyed { id: 2, by_player: None, owner: None, position: Vec2::ZERO },
+            SimulationEvent::PaddleHit { handle: 0 },
+            SimulationEvent::PaddleHit { handle: 1 },
+            SimulationEvent::PaddleHit { handle: 1 },
+            SimulationEvent::BallLost { defender: Some(0) },
+            SimulationEvent::BallLost { defender: None },
+            SimulationEvent::PowerUpCollected { handle: 1 },
+            SimulationEvent::WallHit,
+        ],
+    );
+
+    let first = PlayerStats { bricks_broken: 0, paddle_hits: 1, balls_lost: 1, power_ups: 0 };
+    let second = PlayerStats { bricks_broken: 1, paddle_hits: 2, balls_lost: 0, power_ups: 1 };
+    assert_eq!(stats.players, [first, second]);
+    assert_eq!(stats.team_balls_lost, 1);
+}
+
+#[test]
+fn rallies_end_with_a_serve() {
+    let mut stats = MatchStats::default();
+
+    count_all(&mut stats, &paddle_hits(0, 3));
+    count_all(&mut stats, &[SimulationEvent::BallLost { defender: Some(1) }]);
+    count_all(&mut stats, &paddle_hits(1, 2));
+    assert_eq!(stats.longest_rally, 3, "Losing the ball starts a new rally");
+
+    // Clearing a co-op level serves the ball anew, as does a new round
+    count_all(&mut stats, &paddle_hits(0, 2));
+    count_all(&mut stats, &[SimulationEvent::LevelCleared { level: 2 }]);
+    count_all(&mut stats, &paddle_hits(1, 3));
+    assert_eq!(stats.longest_rally, 4);
+    stats.next_round();
+    count_all(&mut stats, &paddle_hits(0, 2));
+    assert_eq!(stats.longest_rally, 4);
+
+    // Hits of both players make up a rally
+    count_all(&mut stats, &[SimulationEvent::PaddleHit { handle: 1 }, SimulationEvent::PaddleHit { handle: 0 }]);
+    count_all(&mut stats, &paddle_hits(1, 1));
+    assert_eq!(stats.longest_rally, 5);
+}
+
+#[test]
+fn rollbacks_count_from_the_start_of_the_match() {
+    let mut stats = MatchStats::default();
+    stats.count_rollbacks(RollbackStats { rollbacks: 10, resimulated_frames: 40 });
+    assert_eq!(stats.rollbacks, 0, "Rollbacks before the match belong to earlier matches");
+    assert_eq!(stats.average_rollback_frames, 0.0);
+
+    stats.count_rollbacks(RollbackStats { rollbacks: 14, resimulated_frames: 50 });
+    assert_eq!(stats.rollbacks, 4);
+    assert_eq!(stats.average_rollback_frames, 2.5);
+}
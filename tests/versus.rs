@@ -0,0 +1,97 @@
+//! Checks the versus rules: the walls mirror each other, and breaking a wall wins the round for the player
+//! who broke it, with the power-up it drops falling towards them.
+
+use bevy::prelude::*;
+use bifrost::ai::{bot_input, Bots};
+use bifrost::components::{Ball, Brick, PowerUp, Velocity};
+use bifrost::netcode::{start_local_session, FrameCount};
+use bifrost::random::Seed;
+use bifrost::simulation::{
+    build_headless_app, GameMode, RoundOver, Scoreboard, BALL_SIZE, BALL_SPEED, BRICK_SIZE, DIVIDER_WALL,
+};
+
+/// A versus match whose players stand still, a few frames in.
+fn versus_app() -> App {
+    let mut app = App::new();
+    build_headless_app(&mut app, bot_input);
+    app.insert_resource(Seed(7))
+        .insert_resource(GameMode::Versus)
+        .init_resource::<Bots>()
+        .add_startup_system(start_local_session);
+    while app.world.resource::<FrameCount>().frame < 2 {
+        app.update();
+    }
+    app
+}
+
+/// Every brick as its owner, position and whether it drops a power-up.
+fn bricks(app: &mut App) -> Vec<(Option<usize>, Vec2, bool)> {
+    app.world
+        .query::<(&Brick, &Transform)>()
+        .iter(&app.world)
+        .map(|(brick, transform)| (brick.owner, transform.translation.truncate(), brick.power_up))
+        .collect()
+}
+
+#[test]
+fn walls_mirror_each_other() {
+    let mut app = versus_app();
+    let bricks = bricks(&mut app);
+
+    let first: Vec<_> = bricks.iter().filter(|(owner, ..)| *owner == Some(0)).collect();
+    let second: Vec<_> = bricks.iter().filter(|(owner, ..)| *owner == Some(1)).collect();
+    assert!(!first.is_empty(), "Both players should have a wall");
+    assert_eq!(first.len(), second.len());
+    assert_eq!(first.len() + second.len(), bricks.len(), "Every versus brick should belong to a player");
+
+    // Bricks left out and power-ups included, neither player is favored
+    for (_, position, power_up) in first {
+        let mirrored = Vec2::new(position.x, 2.0 * DIVIDER_WALL - position.y);
+        assert!(
+            second.iter().any(|(_, other, other_power_up)| *other == mirrored && other_power_up == power_up),
+            "The brick at {position} should be mirrored at {mirrored}"
+        );
+    }
+}
+
+#[test]
+fn breaking_a_wall_wins_the_round() {
+    let mut app = versus_app();
+
+    // Leave the second player a single brick, far from their paddle, and send the ball straight at it
+    let mut second_wall: Vec<(Entity, Vec3)> = app
+        .world
+        .query::<(Entity, &Brick, &Transform)>()
+        .iter(&app.world)
+        .filter(|(_, brick, _)| brick.owner == Some(1))
+        .map(|(entity, _, transform)| (entity, transform.translation))
+        .collect();
+    second_wall.sort_by(|(_, a), (_, b)| a.x.total_cmp(&b.x));
+    let (last_brick, target) = second_wall.pop().unwrap();
+    for (entity, _) in second_wall {
+        app.world.despawn(entity);
+    }
+    app.world.get_mut::<Brick>(last_brick).unwrap().power_up = true;
+
+    let (mut transform, mut velocity) = app
+        .world
+        .query_filtered::<(&mut Transform, &mut Velocity), With<Ball>>()
+        .single_mut(&mut app.world);
+    let below_target = target.y - (BRICK_SIZE.y + BALL_SIZE.y) / 2.0 - 5.0;
+    transform.translation = Vec3::new(target.x, below_target, transform.translation.z);
+    velocity.0 = Vec2::new(0.0, BALL_SPEED);
+
+    for _ in 0..30 {
+        app.update();
+    }
+
+    let round = app.world.resource::<RoundOver>();
+    assert!(round.frame.is_some(), "The round should be over once a wall is gone");
+    assert_eq!(round.winner, Some(0), "Breaking the second player's wall wins the round for the first");
+    assert_eq!(app.world.resource::<Scoreboard>().players, [1, 0]);
+
+    // The brick became the power-up it dropped, falling towards the player who broke it
+    let (power_up, velocity) = app.world.query::<(&PowerUp, &Velocity)>().single(&app.world);
+    assert_eq!(power_up.for_player, Some(0));
+    assert!(velocity.y < 0.0, "The first player's goal is at the bottom");
+}